@@ -0,0 +1,8 @@
+CREATE TABLE IF NOT EXISTS rate_limiter_buckets (
+    name TEXT PRIMARY KEY,
+    tokens BIGINT NOT NULL,
+    capacity BIGINT NOT NULL,
+    refill_amount BIGINT NOT NULL,
+    refill_interval_ms BIGINT NOT NULL,
+    refilled_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
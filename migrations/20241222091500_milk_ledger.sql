@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS milk_ledger (
+    id BIGSERIAL PRIMARY KEY,
+    client TEXT NOT NULL,
+    unit TEXT,
+    outcome TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS milk_ledger_created_at_idx ON milk_ledger (created_at);
@@ -0,0 +1,5 @@
+ALTER TABLE rate_limiter_buckets ADD COLUMN IF NOT EXISTS initial BIGINT;
+UPDATE rate_limiter_buckets SET initial = capacity WHERE initial IS NULL;
+ALTER TABLE rate_limiter_buckets ALTER COLUMN initial SET NOT NULL;
+
+ALTER TABLE milk_ledger ADD COLUMN IF NOT EXISTS resource TEXT NOT NULL DEFAULT 'milk';
@@ -0,0 +1,4 @@
+ALTER TABLE rate_limiter_buckets ADD COLUMN IF NOT EXISTS algorithm TEXT NOT NULL DEFAULT 'leaky_bucket';
+ALTER TABLE rate_limiter_buckets ADD COLUMN IF NOT EXISTS state JSONB;
+-- `tokens` and `refilled_at` are still read by instances of the previous
+-- release during a rolling deploy; they are dropped in a later release.
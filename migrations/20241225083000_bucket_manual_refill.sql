@@ -0,0 +1 @@
+ALTER TABLE rate_limiter_buckets ADD COLUMN IF NOT EXISTS manually_refilled_at TIMESTAMPTZ;
@@ -0,0 +1,20 @@
+CREATE TABLE IF NOT EXISTS manifests (
+    id UUID PRIMARY KEY,
+    submitter TEXT NOT NULL,
+    package TEXT,
+    format TEXT NOT NULL,
+    body TEXT NOT NULL,
+    created_at TIMESTAMPTZ NOT NULL DEFAULT CURRENT_TIMESTAMP
+);
+
+CREATE INDEX IF NOT EXISTS manifests_created_at_idx ON manifests (created_at);
+
+CREATE TABLE IF NOT EXISTS manifest_orders (
+    manifest_id UUID NOT NULL REFERENCES manifests (id) ON DELETE CASCADE,
+    position INT NOT NULL,
+    item TEXT NOT NULL,
+    quantity NUMERIC NOT NULL,
+    PRIMARY KEY (manifest_id, position)
+);
+
+CREATE INDEX IF NOT EXISTS manifest_orders_item_idx ON manifest_orders (LOWER(item));
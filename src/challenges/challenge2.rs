@@ -1,31 +1,371 @@
-use axum::{extract::Query, routing::get, Router};
-use serde::Deserialize;
-use std::net::{AddrParseError, Ipv6Addr};
+use axum::{
+    body::Body,
+    extract::Query,
+    http::StatusCode,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use futures_util::{stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use std::{
+    convert::Infallible,
+    fmt,
+    net::{AddrParseError, IpAddr, Ipv4Addr, Ipv6Addr},
+};
+use thiserror::Error;
+
+use cidr::{Address, Cidr};
+use cipher::{IpCipher, Mode};
+use format::{Format, Output};
+use port::{Direction, Ports};
+
+mod batch;
+mod cidr;
+mod cipher;
+mod eui64;
+mod format;
+mod info;
+mod port;
+
+/// Most addresses a request may list, as host bits: a /16 of IPv4 or a /112
+/// of IPv6.
+const MAX_LISTED_HOST_BITS: u32 = 16;
+
+/// Listings longer than this many addresses are streamed in chunks this size.
+const STREAM_CHUNK: usize = 1024;
 
 #[derive(Deserialize)]
 struct FromIpToIp {
-    from: String,
-    key: String,
+    from: Option<String>,
+    key: Option<String>,
+    mode: Option<String>,
+    format: Option<String>,
+    port: Option<String>,
+    #[serde(default)]
+    expand: bool,
 }
 
 #[derive(Deserialize)]
 struct FromDestToIp {
-    from: String,
-    to: String,
+    from: Option<String>,
+    to: Option<String>,
+    mode: Option<String>,
+    format: Option<String>,
+    port: Option<String>,
+    #[serde(default)]
+    expand: bool,
+}
+
+#[derive(Deserialize)]
+struct ToIpToSource {
+    to: Option<String>,
+    key: Option<String>,
+    mode: Option<String>,
+    format: Option<String>,
+    port: Option<String>,
+    #[serde(default)]
+    expand: bool,
+}
+
+#[derive(Error, Debug)]
+enum AppError {
+    #[error("Missing parameter `{0}`")]
+    MissingParameter(&'static str),
+
+    #[error("Invalid `{param}` value `{value}`: {reason}")]
+    InvalidAddress {
+        param: &'static str,
+        value: String,
+        reason: String,
+    },
+
+    #[error("`{param}` is an {found} address but this endpoint expects {expected}")]
+    MixedFamilies {
+        param: &'static str,
+        value: String,
+        found: &'static str,
+        expected: &'static str,
+    },
+
+    #[error("Invalid `mode`: {0}")]
+    InvalidMode(String),
+
+    #[error("Invalid `format`: {0}")]
+    InvalidFormat(String),
+
+    #[error("Mode `{0}` cannot recover keys")]
+    NoKeyRecovery(Mode),
+
+    #[error("Invalid `port`: unknown port mode `{0}`, expected transform or keep")]
+    InvalidPortMode(String),
+
+    #[error("Mode `{0}` cannot transform ports, use `port=keep`")]
+    NoPortTransform(Mode),
+
+    #[error("`{0}` cannot have a port when `{1}` is a CIDR block")]
+    PortWithBlock(&'static str, &'static str),
+
+    #[error("Only one of `{0}` and `{1}` may be a CIDR block")]
+    MultipleBlocks(&'static str, &'static str),
+
+    #[error("`{0}` must be a single address in a batch")]
+    BlockInBatch(&'static str),
+
+    #[error("Invalid batch operation: {0}")]
+    InvalidOperation(String),
+
+    #[error("Batch exceeds {0} operations")]
+    BatchTooLarge(usize),
+
+    #[error("Unsupported Content-Type, expected application/json or application/x-ndjson")]
+    UnsupportedContentType,
+
+    #[error("Invalid `mac` value `{value}`: {reason}")]
+    InvalidMac { value: String, reason: String },
+
+    #[error("`{0}` is not a modified EUI-64 address")]
+    NotEui64(String),
+
+    #[error("CIDR block too large to list: `{param}` has more than {limit} addresses")]
+    TooManyAddresses {
+        param: &'static str,
+        value: String,
+        limit: u32,
+    },
+}
+
+/// JSON body of every challenge2 error.
+#[derive(Serialize)]
+struct ErrorBody {
+    error: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    param: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    value: Option<String>,
+}
+
+impl AppError {
+    fn into_parts(self) -> (StatusCode, ErrorBody) {
+        let message = self.to_string();
+        let (status, error, param, value) = match self {
+            AppError::MissingParameter(param) => (
+                StatusCode::BAD_REQUEST,
+                "missing_parameter",
+                Some(param),
+                None,
+            ),
+            AppError::InvalidAddress { param, value, .. } => (
+                StatusCode::BAD_REQUEST,
+                "invalid_address",
+                Some(param),
+                Some(value),
+            ),
+            AppError::MixedFamilies { param, value, .. } => (
+                StatusCode::BAD_REQUEST,
+                "mixed_address_families",
+                Some(param),
+                Some(value),
+            ),
+            AppError::InvalidMode(_) => {
+                (StatusCode::BAD_REQUEST, "invalid_mode", Some("mode"), None)
+            }
+            AppError::InvalidFormat(_) => (
+                StatusCode::BAD_REQUEST,
+                "invalid_format",
+                Some("format"),
+                None,
+            ),
+            AppError::NoKeyRecovery(mode) => (
+                StatusCode::BAD_REQUEST,
+                "no_key_recovery",
+                Some("mode"),
+                Some(mode.to_string()),
+            ),
+            AppError::InvalidPortMode(value) => (
+                StatusCode::BAD_REQUEST,
+                "invalid_port_mode",
+                Some("port"),
+                Some(value),
+            ),
+            AppError::NoPortTransform(mode) => (
+                StatusCode::BAD_REQUEST,
+                "no_port_transform",
+                Some("mode"),
+                Some(mode.to_string()),
+            ),
+            AppError::PortWithBlock(param, _) => (
+                StatusCode::BAD_REQUEST,
+                "port_with_block",
+                Some(param),
+                None,
+            ),
+            AppError::MultipleBlocks(_, param) => (
+                StatusCode::BAD_REQUEST,
+                "multiple_blocks",
+                Some(param),
+                None,
+            ),
+            AppError::BlockInBatch(param) => {
+                (StatusCode::BAD_REQUEST, "block_in_batch", Some(param), None)
+            }
+            AppError::InvalidOperation(_) => {
+                (StatusCode::BAD_REQUEST, "invalid_operation", None, None)
+            }
+            AppError::BatchTooLarge(_) => {
+                (StatusCode::PAYLOAD_TOO_LARGE, "batch_too_large", None, None)
+            }
+            AppError::UnsupportedContentType => (
+                StatusCode::UNSUPPORTED_MEDIA_TYPE,
+                "unsupported_content_type",
+                None,
+                None,
+            ),
+            AppError::InvalidMac { value, .. } => (
+                StatusCode::BAD_REQUEST,
+                "invalid_mac",
+                Some("mac"),
+                Some(value),
+            ),
+            AppError::NotEui64(value) => (
+                StatusCode::BAD_REQUEST,
+                "not_eui64",
+                Some("address"),
+                Some(value),
+            ),
+            AppError::TooManyAddresses { param, value, .. } => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                "too_many_addresses",
+                Some(param),
+                Some(value),
+            ),
+        };
+
+        let body = ErrorBody {
+            error,
+            message,
+            param,
+            value,
+        };
+        (status, body)
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let (status, body) = self.into_parts();
+        (status, Json(body)).into_response()
+    }
 }
+
+/// A required query parameter, paired with its name for error reporting.
+fn required<'a>(
+    param: &'static str,
+    value: &'a Option<String>,
+) -> Result<(&'static str, &'a str), AppError> {
+    value
+        .as_deref()
+        .map(|value| (param, value))
+        .ok_or(AppError::MissingParameter(param))
+}
+
+/// The cipher chosen by `mode`, or the family's original one.
+fn cipher<A: Address>(
+    mode: &Option<String>,
+    default: Mode,
+) -> Result<(Mode, &'static dyn IpCipher<A>), AppError> {
+    let mode = match mode {
+        Some(mode) => mode.parse().map_err(AppError::InvalidMode)?,
+        None => default,
+    };
+    Ok((mode, mode.cipher()))
+}
+
+fn output_format(format: &Option<String>) -> Result<Format, AppError> {
+    match format {
+        Some(format) => format.parse().map_err(AppError::InvalidFormat),
+        None => Ok(Format::default()),
+    }
+}
+
+/// `::ffff:1.2.3.4` as `1.2.3.4`, or `::ffff:10.0.0.0/104` as `10.0.0.0/8`,
+/// when `A` is IPv4.
+fn unmap<A: Address>(value: &str) -> Option<String> {
+    if A::BITS != 32 {
+        return None;
+    }
+    let (address, prefix) = match value.split_once('/') {
+        Some((address, prefix)) => (address, Some(prefix)),
+        None => (value, None),
+    };
+    let address = address.parse::<Ipv6Addr>().ok()?.to_ipv4_mapped()?;
+    match prefix {
+        Some(prefix) => {
+            let prefix = prefix.parse::<u32>().ok()?.checked_sub(96)?;
+            Some(format!("{}/{}", address, prefix))
+        }
+        None => Some(address.to_string()),
+    }
+}
+
+/// Parse a query parameter as an address or CIDR block of family `A`,
+/// explaining clearly when it is an address of the other family. IPv4 may
+/// also be given IPv4-mapped; the flag says whether it was.
+fn parse_block<A: Address>(
+    (param, value): (&'static str, &str),
+) -> Result<(Cidr<A>, bool), AppError> {
+    if let Some(unmapped) = unmap::<A>(value) {
+        if let Ok(block) = Cidr::parse(&unmapped) {
+            return Ok((block, true));
+        }
+    }
+    Cidr::parse(value)
+        .map(|block| (block, false))
+        .map_err(|reason| {
+            let address = value.split_once('/').map_or(value, |(address, _)| address);
+            let found = match address.parse::<IpAddr>() {
+                Ok(IpAddr::V4(_)) => "IPv4",
+                Ok(IpAddr::V6(_)) => "IPv6",
+                Err(_) => A::FAMILY,
+            };
+            if found != A::FAMILY {
+                AppError::MixedFamilies {
+                    param,
+                    value: value.to_string(),
+                    found,
+                    expected: A::FAMILY,
+                }
+            } else {
+                AppError::InvalidAddress {
+                    param,
+                    value: value.to_string(),
+                    reason,
+                }
+            }
+        })
+}
+
+/// Like [`parse_block`], but also accepting a socket address such as
+/// `1.2.3.4:8080` or `[fe80::1]:443`, whose port is returned separately.
+fn parse_socket<A: Address>(
+    (param, value): (&'static str, &str),
+) -> Result<(Cidr<A>, bool, Option<u16>), AppError> {
+    let (address, port) = port::split_port(value).map_err(|reason| AppError::InvalidAddress {
+        param,
+        value: value.to_string(),
+        reason,
+    })?;
+    let (block, mapped) = parse_block::<A>((param, address))?;
+    Ok((block, mapped, port))
+}
+
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub struct Ipv4 {
     octets: [u8; 4],
 }
 
 impl Ipv4 {
-    /// Create a new Ipv4 instance from four octets.
-    pub fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
-        Ipv4 {
-            octets: [a, b, c, d],
-        }
-    }
-
     /// Create an Ipv4 instance from a standard IPv4 string like "192.168.0.1".
     pub fn from_str(ip_str: &str) -> Result<Self, AddrParseError> {
         // We can leverage the standard library's parsing first.
@@ -34,19 +374,18 @@ impl Ipv4 {
             octets: addr.octets(),
         })
     }
+}
 
-    /// Return the octets as a [u8;4].
-    pub fn octets(&self) -> [u8; 4] {
-        self.octets
+/// Lane-wise arithmetic, the reference the ciphers are checked against.
+#[cfg(test)]
+impl Ipv4 {
+    /// Create a new Ipv4 instance from four octets.
+    pub fn new(a: u8, b: u8, c: u8, d: u8) -> Self {
+        Ipv4 {
+            octets: [a, b, c, d],
+        }
     }
 
-    /// Convert the IPv4 address back to a dot-decimal string like "192.168.0.1".
-    pub fn to_string(&self) -> String {
-        format!(
-            "{}.{}.{}.{}",
-            self.octets[0], self.octets[1], self.octets[2], self.octets[3]
-        )
-    }
     pub fn add(&self, other: &Ipv4) -> Ipv4 {
         Ipv4 {
             octets: [
@@ -69,20 +408,47 @@ impl Ipv4 {
     }
 }
 
-pub struct Ipv6 {
-    segments: [u16; 8],
+impl fmt::Display for Ipv4 {
+    /// Write the IPv4 address as a dot-decimal string like "192.168.0.1".
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}.{}.{}.{}",
+            self.octets[0], self.octets[1], self.octets[2], self.octets[3]
+        )
+    }
 }
 
-impl Ipv6 {
-    /// Create a new Ipv6 instance from eight 16-bit segments.
-    ///
-    /// For example: `Ipv6::new(0xfe80, 0, 0, 0, 0, 0, 0, 1)` represents `fe80::1`
-    pub fn new(s1: u16, s2: u16, s3: u16, s4: u16, s5: u16, s6: u16, s7: u16, s8: u16) -> Self {
-        Ipv6 {
-            segments: [s1, s2, s3, s4, s5, s6, s7, s8],
+impl Address for Ipv4 {
+    const FAMILY: &'static str = "IPv4";
+    const BITS: u32 = 32;
+    const LANE_BITS: u32 = 8;
+
+    fn parse(s: &str) -> Result<Self, AddrParseError> {
+        Ipv4::from_str(s)
+    }
+
+    fn to_bits(&self) -> u128 {
+        u32::from_be_bytes(self.octets).into()
+    }
+
+    fn from_bits(bits: u128) -> Self {
+        Ipv4 {
+            octets: (bits as u32).to_be_bytes(),
         }
     }
 
+    fn to_ipv6(&self) -> Ipv6Addr {
+        Ipv4Addr::from(self.octets).to_ipv6_mapped()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Ipv6 {
+    segments: [u16; 8],
+}
+
+impl Ipv6 {
     /// Parse an IPv6 address from a string, e.g. "fe80::1"
     pub fn from_str(ip_str: &str) -> Result<Self, AddrParseError> {
         let addr = ip_str.parse::<Ipv6Addr>()?;
@@ -91,58 +457,260 @@ impl Ipv6 {
         })
     }
 
-    /// Get the internal segments.
-    pub fn segments(&self) -> [u16; 8] {
-        self.segments
+    /// The SLAAC address for `mac` in the /64 starting at `prefix`: the upper
+    /// 64 bits of `prefix` followed by the modified EUI-64 interface
+    /// identifier (RFC 4291, appendix A), which is the MAC split in half
+    /// around `ff:fe` with the universal/local bit inverted.
+    pub fn from_eui64(prefix: &Ipv6, mac: [u8; 6]) -> Ipv6 {
+        let identifier = [
+            mac[0] ^ 0x02,
+            mac[1],
+            mac[2],
+            0xff,
+            0xfe,
+            mac[3],
+            mac[4],
+            mac[5],
+        ];
+        let network = prefix.to_bits() & !u128::from(u64::MAX);
+        Ipv6::from_bits(network | u128::from(u64::from_be_bytes(identifier)))
+    }
+
+    /// The MAC a SLAAC address was built from, or `None` when its interface
+    /// identifier is not a modified EUI-64.
+    pub fn eui64_mac(&self) -> Option<[u8; 6]> {
+        let identifier = (self.to_bits() as u64).to_be_bytes();
+        if identifier[3..5] != [0xff, 0xfe] {
+            return None;
+        }
+        Some([
+            identifier[0] ^ 0x02,
+            identifier[1],
+            identifier[2],
+            identifier[5],
+            identifier[6],
+            identifier[7],
+        ])
     }
+}
 
-    /// Convert the IPv6 address back to a standard IPv6 string.
+impl fmt::Display for Ipv6 {
+    /// Write the IPv6 address as a standard IPv6 string.
     /// This will produce a compressed form if possible, e.g. "fe80::1".
-    pub fn to_string(&self) -> String {
-        let addr = Ipv6Addr::from(self.segments);
-        addr.to_string()
-    }
-    pub fn xor(&self, other: &Ipv6) -> Ipv6 {
-        let mut result = [0_u16; 8];
-        for i in 0..8 {
-            result[i] = self.segments[i] ^ other.segments[i];
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", Ipv6Addr::from(self.segments))
+    }
+}
+
+impl Address for Ipv6 {
+    const FAMILY: &'static str = "IPv6";
+    const BITS: u32 = 128;
+    const LANE_BITS: u32 = 16;
+
+    fn parse(s: &str) -> Result<Self, AddrParseError> {
+        Ipv6::from_str(s)
+    }
+
+    fn to_bits(&self) -> u128 {
+        Ipv6Addr::from(self.segments).to_bits()
+    }
+
+    fn from_bits(bits: u128) -> Self {
+        Ipv6 {
+            segments: Ipv6Addr::from_bits(bits).segments(),
         }
-        Ipv6 { segments: result }
+    }
+
+    fn to_ipv6(&self) -> Ipv6Addr {
+        Ipv6Addr::from(self.segments)
     }
 }
 
-async fn calculate_ipv5_sum(Query(source_dest): Query<FromIpToIp>) -> String {
-    let from = Ipv4::from_str(&source_dest.from).unwrap();
-    let key = Ipv4::from_str(&source_dest.key).unwrap();
+/// Apply `op` to two addresses, one of which may be a CIDR block. A block is
+/// answered with the block it maps to when `op` is lane-wise and the image is
+/// a block, and otherwise, or when `expand` is set, with every mapped address,
+/// one per line. Socket addresses are answered with a socket address, its
+/// port given by `ports`.
+fn transform<A: Address>(
+    left: (&'static str, &str),
+    right: (&'static str, &str),
+    expand: bool,
+    format: Format,
+    lane_wise: bool,
+    ports: Ports,
+    op: impl Fn(A, A) -> A + Copy + Send + 'static,
+) -> Result<Response, AppError> {
+    let (left_block, left_mapped, left_port) = parse_socket::<A>(left)?;
+    let (right_block, right_mapped, right_port) = parse_socket::<A>(right)?;
+    let output = Output {
+        format,
+        mapped: left_mapped || right_mapped,
+    };
+    let block_param = match (left_block.is_single(), right_block.is_single()) {
+        (true, true) => None,
+        (false, _) => Some(left.0),
+        (_, false) => Some(right.0),
+    };
+    if let Some(block_param) = block_param {
+        if left_port.is_some() {
+            return Err(AppError::PortWithBlock(left.0, block_param));
+        }
+        if right_port.is_some() {
+            return Err(AppError::PortWithBlock(right.0, block_param));
+        }
+    }
+    let (block, (param, value), f): (_, _, Box<dyn Fn(A) -> A + Send>) =
+        match (left_block.is_single(), right_block.is_single()) {
+            (true, true) => {
+                let result = op(left_block.network(), right_block.network());
+                let port = ports.apply(left_port, right_port)?;
+                return Ok(output.socket(result, port).into_response());
+            }
+            (false, false) => return Err(AppError::MultipleBlocks(left.0, right.0)),
+            (false, true) => (
+                left_block,
+                left,
+                Box::new(move |address| op(address, right_block.network())),
+            ),
+            (true, false) => (
+                right_block,
+                right,
+                Box::new(move |address| op(left_block.network(), address)),
+            ),
+        };
 
-    format!("{}", from.add(&key).to_string())
+    if !expand && lane_wise {
+        if let Some(image) = block.map(&f) {
+            return Ok(output.block(image).into_response());
+        }
+    }
+    if block.host_bits() > MAX_LISTED_HOST_BITS {
+        return Err(AppError::TooManyAddresses {
+            param,
+            value: value.to_string(),
+            limit: 1 << MAX_LISTED_HOST_BITS,
+        });
+    }
+    let lines = block
+        .addresses()
+        .map(move |address| output.address(f(address)) + "\n");
+    if block.host_bits() <= STREAM_CHUNK.ilog2() {
+        return Ok(lines.collect::<String>().into_response());
+    }
+    let chunks = stream::iter(lines)
+        .chunks(STREAM_CHUNK)
+        .map(|lines| Ok::<_, Infallible>(lines.concat()));
+    Ok(Body::from_stream(chunks).into_response())
 }
 
-async fn calculate_ipv5_sub(Query(dest_source): Query<FromDestToIp>) -> String {
-    let from = Ipv4::from_str(&dest_source.from).unwrap();
-    let to = Ipv4::from_str(&dest_source.to).unwrap();
+fn encrypt<A: Address>(query: FromIpToIp, default: Mode) -> Result<Response, AppError> {
+    let (mode, cipher) = cipher::<A>(&query.mode, default)?;
+    transform(
+        required("from", &query.from)?,
+        required("key", &query.key)?,
+        query.expand,
+        output_format(&query.format)?,
+        cipher.lane_wise(),
+        Ports::new(&query.port, mode, Direction::Encrypt)?,
+        move |from, key| cipher.encrypt(from, key),
+    )
+}
 
-    format!("{}", to.sub(&from).to_string())
+fn decrypt<A: Address>(query: ToIpToSource, default: Mode) -> Result<Response, AppError> {
+    let (mode, cipher) = cipher::<A>(&query.mode, default)?;
+    transform(
+        required("to", &query.to)?,
+        required("key", &query.key)?,
+        query.expand,
+        output_format(&query.format)?,
+        cipher.lane_wise(),
+        Ports::new(&query.port, mode, Direction::Decrypt)?,
+        move |to, key| cipher.decrypt(to, key),
+    )
 }
 
-async fn calculate_ipv6_sum(Query(source_dest): Query<FromIpToIp>) -> String {
-    let from = Ipv6::from_str(&source_dest.from).unwrap();
-    let key = Ipv6::from_str(&source_dest.key).unwrap();
+fn recover_key<A: Address>(query: FromDestToIp, default: Mode) -> Result<Response, AppError> {
+    let (mode, cipher) = cipher::<A>(&query.mode, default)?;
+    // Ciphers either recover keys for every pair or for none.
+    let zero = A::from_bits(0);
+    if cipher.recover_key(zero, zero).is_none() {
+        return Err(AppError::NoKeyRecovery(mode));
+    }
+    transform(
+        required("from", &query.from)?,
+        required("to", &query.to)?,
+        query.expand,
+        output_format(&query.format)?,
+        cipher.lane_wise(),
+        Ports::new(&query.port, mode, Direction::RecoverKey)?,
+        move |from, to| cipher.recover_key(from, to).expect("cipher recovers keys"),
+    )
+}
+
+async fn calculate_ipv5_sum(Query(source_dest): Query<FromIpToIp>) -> Result<Response, AppError> {
+    encrypt::<Ipv4>(source_dest, Mode::Octet)
+}
+
+async fn calculate_ipv5_sub(Query(dest_source): Query<FromDestToIp>) -> Result<Response, AppError> {
+    recover_key::<Ipv4>(dest_source, Mode::Octet)
+}
+
+async fn calculate_ipv5_source(Query(dest_key): Query<ToIpToSource>) -> Result<Response, AppError> {
+    decrypt::<Ipv4>(dest_key, Mode::Octet)
+}
 
-    format!("{}", from.xor(&key).to_string())
+async fn calculate_ipv6_sum(Query(source_dest): Query<FromIpToIp>) -> Result<Response, AppError> {
+    encrypt::<Ipv6>(source_dest, Mode::Xor)
 }
 
-async fn calculate_ipv6_sub(Query(dest_source): Query<FromDestToIp>) -> String {
-    let from = Ipv6::from_str(&dest_source.from).unwrap();
-    let to = Ipv6::from_str(&dest_source.to).unwrap();
+async fn calculate_ipv6_sub(Query(dest_source): Query<FromDestToIp>) -> Result<Response, AppError> {
+    recover_key::<Ipv6>(dest_source, Mode::Xor)
+}
 
-    format!("{}", to.xor(&from).to_string())
+async fn calculate_ipv6_source(Query(dest_key): Query<ToIpToSource>) -> Result<Response, AppError> {
+    decrypt::<Ipv6>(dest_key, Mode::Xor)
 }
 
 pub fn router() -> Router {
     Router::new()
         .route("/dest", get(calculate_ipv5_sum))
         .route("/key", get(calculate_ipv5_sub))
+        .route("/source", get(calculate_ipv5_source))
         .route("/v6/dest", get(calculate_ipv6_sum))
         .route("/v6/key", get(calculate_ipv6_sub))
+        .route("/v6/source", get(calculate_ipv6_source))
+        .route("/v6/eui64", get(eui64::slaac_address))
+        .route("/v6/mac", get(eui64::recover_mac))
+        .route("/info", get(info::subnet_info))
+        .route("/batch", post(batch::run_batch))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Uri;
+
+    fn dest(uri: &'static str) -> Result<Response, AppError> {
+        encrypt::<Ipv4>(
+            Query::try_from_uri(&Uri::from_static(uri)).unwrap().0,
+            Mode::Octet,
+        )
+    }
+
+    #[test]
+    fn ports_are_rejected_on_either_side_of_a_block() {
+        assert!(matches!(
+            dest("/2/dest?from=10.0.0.0/24:80&key=1.2.3.4"),
+            Err(AppError::PortWithBlock("from", "from"))
+        ));
+        assert!(matches!(
+            dest("/2/dest?from=10.0.0.1:80&key=1.2.3.0/24"),
+            Err(AppError::PortWithBlock("from", "key"))
+        ));
+        assert!(matches!(
+            dest("/2/dest?from=10.0.0.0/24&key=1.2.3.4:80"),
+            Err(AppError::PortWithBlock("key", "from"))
+        ));
+        assert!(dest("/2/dest?from=10.0.0.1:80&key=1.2.3.4").is_ok());
+    }
 }
@@ -0,0 +1,242 @@
+use axum::{
+    body::{Body, Bytes},
+    extract::Request,
+    http::header::CONTENT_TYPE,
+    response::{IntoResponse, Response},
+    RequestExt,
+};
+use futures_util::{stream, Stream, StreamExt};
+use serde::Deserialize;
+use serde_json::json;
+use std::{convert::Infallible, net::IpAddr};
+
+use super::{
+    cipher, output_format, parse_socket, port::split_port, required, Address, AppError, Direction,
+    Ipv4, Ipv6, Mode, Output, Ports,
+};
+
+/// Most operations one batch may hold.
+const MAX_OPERATIONS: usize = 100_000;
+
+/// Longest NDJSON line accepted, in bytes.
+const MAX_LINE: usize = 4096;
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Operation {
+    /// Encrypt `from` with `key`, like `/2/dest`.
+    Dest,
+    /// Recover the key taking `from` to `to`, like `/2/key`.
+    Key,
+    /// Decrypt `to` with `key`, like `/2/source`.
+    Source,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Family {
+    V4,
+    V6,
+}
+
+/// One operation of a `POST /2/batch` request, with the same parameters as
+/// the matching GET endpoint. The family is inferred from the addresses when
+/// not given, with IPv4-mapped addresses counting as IPv4.
+#[derive(Debug, Deserialize)]
+struct BatchOperation {
+    op: Operation,
+    family: Option<Family>,
+    from: Option<String>,
+    key: Option<String>,
+    to: Option<String>,
+    mode: Option<String>,
+    format: Option<String>,
+    port: Option<String>,
+}
+
+impl BatchOperation {
+    fn run(&self) -> Result<String, AppError> {
+        match self.family.unwrap_or_else(|| self.infer_family()) {
+            Family::V4 => self.run_as::<Ipv4>(Mode::Octet),
+            Family::V6 => self.run_as::<Ipv6>(Mode::Xor),
+        }
+    }
+
+    fn infer_family(&self) -> Family {
+        let first = [&self.from, &self.to, &self.key]
+            .into_iter()
+            .find_map(|address| {
+                let (address, _) = split_port(address.as_deref()?).ok()?;
+                address.parse::<IpAddr>().ok()
+            });
+        match first {
+            Some(IpAddr::V6(address)) if address.to_ipv4_mapped().is_none() => Family::V6,
+            _ => Family::V4,
+        }
+    }
+
+    fn run_as<A: Address>(&self, default: Mode) -> Result<String, AppError> {
+        let (mode, cipher) = cipher::<A>(&self.mode, default)?;
+        let format = output_format(&self.format)?;
+        let address = |param, value| {
+            let (block, mapped, port) = parse_socket::<A>(required(param, value)?)?;
+            if !block.is_single() {
+                return Err(AppError::BlockInBatch(param));
+            }
+            Ok((block.network(), mapped, port))
+        };
+        let (left, right) = match self.op {
+            Operation::Dest => (address("from", &self.from)?, address("key", &self.key)?),
+            Operation::Source => (address("to", &self.to)?, address("key", &self.key)?),
+            Operation::Key => (address("from", &self.from)?, address("to", &self.to)?),
+        };
+        let (result, direction) = match self.op {
+            Operation::Dest => (cipher.encrypt(left.0, right.0), Direction::Encrypt),
+            Operation::Source => (cipher.decrypt(left.0, right.0), Direction::Decrypt),
+            Operation::Key => (
+                cipher
+                    .recover_key(left.0, right.0)
+                    .ok_or(AppError::NoKeyRecovery(mode))?,
+                Direction::RecoverKey,
+            ),
+        };
+        let port = Ports::new(&self.port, mode, direction)?.apply(left.2, right.2)?;
+        let output = Output {
+            format,
+            mapped: left.1 || right.1,
+        };
+        Ok(output.socket(result, port))
+    }
+}
+
+/// One output line: `{"result": ...}` or the error the single endpoint would
+/// have returned.
+fn result_line(operation: Result<BatchOperation, AppError>) -> String {
+    let line = match operation.and_then(|operation| operation.run()) {
+        Ok(result) => json!({ "result": result }),
+        Err(e) => json!(e.into_parts().1),
+    };
+    line.to_string() + "\n"
+}
+
+fn parse_operation(value: serde_json::Value) -> Result<BatchOperation, AppError> {
+    serde_json::from_value(value).map_err(|e| AppError::InvalidOperation(e.to_string()))
+}
+
+/// Split a body into lines as it arrives, without buffering more than one
+/// line.
+fn body_lines(body: Body) -> impl Stream<Item = Result<String, AppError>> + Send {
+    let state = (body.into_data_stream(), Vec::new(), false);
+    stream::unfold(state, |(mut chunks, mut buffer, mut done)| async move {
+        loop {
+            if let Some(end) = buffer.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=end).collect();
+                let line = String::from_utf8_lossy(&line).trim().to_string();
+                return Some((Ok(line), (chunks, buffer, done)));
+            }
+            if buffer.len() > MAX_LINE {
+                let reason = format!("line exceeds {} bytes", MAX_LINE);
+                let error = AppError::InvalidOperation(reason);
+                return Some((Err(error), (chunks, Vec::new(), true)));
+            }
+            if done {
+                if buffer.is_empty() {
+                    return None;
+                }
+                let line = String::from_utf8_lossy(&buffer).trim().to_string();
+                return Some((Ok(line), (chunks, Vec::new(), true)));
+            }
+            match chunks.next().await {
+                Some(Ok(chunk)) => buffer.extend_from_slice(&chunk),
+                Some(Err(e)) => {
+                    let error = AppError::InvalidOperation(e.to_string());
+                    return Some((Err(error), (chunks, Vec::new(), true)));
+                }
+                None => done = true,
+            }
+        }
+    })
+}
+
+/// `POST /2/batch`: run a JSON array or NDJSON stream of operations and
+/// stream back one NDJSON result per operation, in order. NDJSON is read as
+/// it arrives, so batches of any size run in constant memory, up to
+/// [`MAX_OPERATIONS`].
+pub(super) async fn run_batch(req: Request) -> Result<Response, AppError> {
+    let media_type = req
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase());
+
+    let results = match media_type.as_deref() {
+        Some("application/json") => {
+            let body = req
+                .extract::<Bytes, _>()
+                .await
+                .map_err(|e| AppError::InvalidOperation(e.body_text()))?;
+            let operations: Vec<serde_json::Value> = serde_json::from_slice(&body)
+                .map_err(|e| AppError::InvalidOperation(e.to_string()))?;
+            if operations.len() > MAX_OPERATIONS {
+                return Err(AppError::BatchTooLarge(MAX_OPERATIONS));
+            }
+            stream::iter(operations)
+                .map(|operation| result_line(parse_operation(operation)))
+                .boxed()
+        }
+        Some("application/x-ndjson" | "application/jsonl") => body_lines(req.into_body())
+            .filter(|line| std::future::ready(!matches!(line, Ok(line) if line.is_empty())))
+            .take(MAX_OPERATIONS + 1)
+            .enumerate()
+            .map(|(index, line)| {
+                if index == MAX_OPERATIONS {
+                    return result_line(Err(AppError::BatchTooLarge(MAX_OPERATIONS)));
+                }
+                result_line(line.and_then(|line| {
+                    serde_json::from_str(&line)
+                        .map_err(|e| AppError::InvalidOperation(e.to_string()))
+                }))
+            })
+            .boxed(),
+        _ => return Err(AppError::UnsupportedContentType),
+    };
+
+    let body = Body::from_stream(results.map(Ok::<_, Infallible>));
+    Ok(([(CONTENT_TYPE, "application/x-ndjson")], body).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn lines_may_span_chunks() {
+        let chunks: Vec<Result<_, Infallible>> = vec![
+            Ok(Bytes::from("{\"op\":\"dest\",\"from\":\"10.0.")),
+            Ok(Bytes::from("0.0\",\"key\":\"1.2.3.255\"}\n\n{\"op\"")),
+            Ok(Bytes::from(
+                ":\"key\",\"from\":\"fe80::1\",\"to\":\"::1\"}\n",
+            )),
+            Ok(Bytes::from(
+                "{\"op\":\"dest\",\"from\":\"[fe80::1]:443\",\"key\":\"::1:1\"}",
+            )),
+        ];
+        let lines: Vec<_> = body_lines(Body::from_stream(stream::iter(chunks)))
+            .map(|line| line.unwrap())
+            .collect()
+            .await;
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[1], "");
+
+        let first = serde_json::from_str(&lines[0]).unwrap();
+        assert_eq!(result_line(Ok(first)), "{\"result\":\"11.2.3.255\"}\n");
+        let last = serde_json::from_str(&lines[2]).unwrap();
+        assert_eq!(result_line(Ok(last)), "{\"result\":\"fe80::\"}\n");
+        let socket = serde_json::from_str(&lines[3]).unwrap();
+        assert_eq!(
+            result_line(Ok(socket)),
+            "{\"result\":\"[fe80::1:0]:443\"}\n"
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use std::{
+    fmt,
+    net::{AddrParseError, Ipv6Addr},
+};
+
+/// An IP address viewed as a fixed-width integer made of lanes, the units the
+/// /2 operations act on independently: octets for IPv4, segments for IPv6.
+pub trait Address: Copy + fmt::Display + Send + 'static {
+    /// "IPv4" or "IPv6", for error messages.
+    const FAMILY: &'static str;
+    const BITS: u32;
+    const LANE_BITS: u32;
+
+    fn parse(s: &str) -> Result<Self, AddrParseError>;
+    fn to_bits(&self) -> u128;
+    fn from_bits(bits: u128) -> Self;
+
+    /// The address as IPv6, IPv4-mapped if it is IPv4.
+    fn to_ipv6(&self) -> Ipv6Addr;
+}
+
+/// A CIDR block such as `10.0.0.0/24`. A bare address is a block of one.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Cidr<A> {
+    network: A,
+    prefix: u32,
+}
+
+impl<A: Address> Cidr<A> {
+    /// Parse `address/prefix` or a bare address. Host bits are cleared, so
+    /// `10.0.0.7/24` is `10.0.0.0/24`. The error says what is wrong.
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let (address, prefix) = match s.split_once('/') {
+            Some((address, prefix)) => match prefix.parse() {
+                Ok(length) if length <= A::BITS => (address, length),
+                Ok(length) => {
+                    return Err(format!("prefix length {} exceeds {} bits", length, A::BITS))
+                }
+                Err(_) => return Err(format!("invalid prefix length `{}`", prefix)),
+            },
+            None => (s, A::BITS),
+        };
+        let address = A::parse(address).map_err(|e| e.to_string())?;
+        Ok(Cidr {
+            network: A::from_bits(address.to_bits() & !host_mask(A::BITS - prefix)),
+            prefix,
+        })
+    }
+
+    pub fn network(&self) -> A {
+        self.network
+    }
+
+    pub fn prefix(&self) -> u32 {
+        self.prefix
+    }
+
+    pub fn host_bits(&self) -> u32 {
+        A::BITS - self.prefix
+    }
+
+    pub fn is_single(&self) -> bool {
+        self.prefix == A::BITS
+    }
+
+    /// The address with every network bit set, e.g. `255.255.255.0` for a /24.
+    pub fn netmask(&self) -> A {
+        A::from_bits(host_mask(A::BITS) & !host_mask(self.host_bits()))
+    }
+
+    /// The highest address in the block.
+    pub fn last(&self) -> A {
+        A::from_bits(self.network.to_bits() | host_mask(self.host_bits()))
+    }
+
+    /// The block of the same size just above this one, unless this one ends
+    /// the address space.
+    pub fn next(&self) -> Option<Self> {
+        let network = self.last().to_bits().checked_add(1)?;
+        (network <= host_mask(A::BITS)).then(|| Cidr {
+            network: A::from_bits(network),
+            prefix: self.prefix,
+        })
+    }
+
+    /// The block of the same size just below this one, unless this one starts
+    /// the address space.
+    pub fn previous(&self) -> Option<Self> {
+        let network = self.network.to_bits().checked_sub(1)? & !host_mask(self.host_bits());
+        Some(Cidr {
+            network: A::from_bits(network),
+            prefix: self.prefix,
+        })
+    }
+
+    /// Every address in the block, in order.
+    pub fn addresses(&self) -> impl Iterator<Item = A> + Send + 'static {
+        let network = self.network.to_bits();
+        (0..=host_mask(self.host_bits())).map(move |offset| A::from_bits(network | offset))
+    }
+
+    /// The image of the block under `f`, if that is itself a block of the same
+    /// size. `f` must be a bijection acting on each lane independently, as
+    /// every /2 operation is; then each lane can be checked on its own.
+    pub fn map(&self, f: impl Fn(A) -> A) -> Option<Self> {
+        let network = self.network.to_bits();
+        let lane_mask = (1u128 << A::LANE_BITS) - 1;
+        let mut image = 0;
+        for lane in 0..A::BITS / A::LANE_BITS {
+            let shift = A::BITS - (lane + 1) * A::LANE_BITS;
+            let count = 1u128 << self.host_bits().saturating_sub(shift).min(A::LANE_BITS);
+            let (mut low, mut high) = (u128::MAX, 0);
+            for value in 0..count {
+                let mapped =
+                    f(A::from_bits(network | value << shift)).to_bits() >> shift & lane_mask;
+                low = low.min(mapped);
+                high = high.max(mapped);
+            }
+            if low % count != 0 || high - low != count - 1 {
+                return None;
+            }
+            image |= low << shift;
+        }
+        Some(Cidr {
+            network: A::from_bits(image),
+            prefix: self.prefix,
+        })
+    }
+}
+
+fn host_mask(host_bits: u32) -> u128 {
+    u128::MAX.checked_shr(128 - host_bits).unwrap_or(0)
+}
+
+impl<A: Address> fmt::Display for Cidr<A> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}", self.network, self.prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenges::challenge2::Ipv4;
+    use std::collections::BTreeSet;
+
+    fn block(s: &str) -> Cidr<Ipv4> {
+        Cidr::parse(s).unwrap()
+    }
+
+    #[test]
+    fn parse_clears_host_bits() {
+        assert_eq!(block("10.0.0.7/24").to_string(), "10.0.0.0/24");
+        assert!(block("10.0.0.7").is_single());
+        assert_eq!(
+            Cidr::<Ipv4>::parse("10.0.0.0/33"),
+            Err("prefix length 33 exceeds 32 bits".to_string())
+        );
+        assert_eq!(
+            Cidr::<Ipv4>::parse("10.0.0/8"),
+            Err("invalid IPv4 address syntax".to_string())
+        );
+    }
+
+    #[test]
+    fn map_agrees_with_listing_every_address() {
+        for (network, key) in [
+            ("10.0.0.0/24", Ipv4::new(1, 2, 3, 4)),
+            ("10.0.0.0/30", Ipv4::new(1, 2, 3, 4)),
+            ("10.0.0.0/30", Ipv4::new(1, 2, 3, 255)),
+            ("10.0.4.0/22", Ipv4::new(0, 0, 2, 9)),
+            ("10.0.4.0/22", Ipv4::new(0, 0, 4, 9)),
+        ] {
+            let block = block(network);
+            let add = |address: Ipv4| address.add(&key);
+            let image: BTreeSet<_> = block.addresses().map(|a| add(a).to_bits()).collect();
+            let contiguous = image.len() as u128
+                == image.last().unwrap() - image.first().unwrap() + 1
+                && image.first().unwrap() % image.len() as u128 == 0;
+            match block.map(add) {
+                Some(mapped) => {
+                    assert!(contiguous, "{} + {}", network, key);
+                    assert_eq!(mapped.network().to_bits(), *image.first().unwrap());
+                }
+                None => assert!(!contiguous, "{} + {}", network, key),
+            }
+        }
+    }
+
+    #[test]
+    fn neighbours_stop_at_the_ends_of_the_address_space() {
+        let middle = block("10.0.1.0/24");
+        assert_eq!(middle.netmask(), Ipv4::new(255, 255, 255, 0));
+        assert_eq!(middle.last(), Ipv4::new(10, 0, 1, 255));
+        assert_eq!(middle.next(), Some(block("10.0.2.0/24")));
+        assert_eq!(middle.previous(), Some(block("10.0.0.0/24")));
+
+        assert_eq!(block("0.0.0.0/1").previous(), None);
+        assert_eq!(block("128.0.0.0/1").next(), None);
+        assert_eq!(block("0.0.0.0/0").next(), None);
+        assert_eq!(block("0.0.0.0/0").netmask(), Ipv4::new(0, 0, 0, 0));
+    }
+}
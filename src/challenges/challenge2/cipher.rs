@@ -0,0 +1,315 @@
+use std::{fmt, str::FromStr};
+
+use super::cidr::Address;
+
+/// A way of "encrypting" one address with another as the key.
+///
+/// Every cipher is a permutation of the address space for each key, so
+/// `decrypt` exactly undoes `encrypt`. Ciphers work on the address bits alone
+/// and so serve IPv4 and IPv6 alike.
+pub trait IpCipher<A: Address>: Send + Sync {
+    fn encrypt(&self, address: A, key: A) -> A;
+
+    fn decrypt(&self, address: A, key: A) -> A;
+
+    /// The key that encrypts `from` to `to`, for ciphers where it is unique.
+    /// Ciphers that can recover keys can do so for every pair.
+    fn recover_key(&self, _from: A, _to: A) -> Option<A> {
+        None
+    }
+
+    /// Whether the cipher acts on each [`Address::LANE_BITS`] lane
+    /// independently, which lets CIDR blocks map to blocks.
+    fn lane_wise(&self) -> bool {
+        false
+    }
+}
+
+/// The cipher selected by the `mode` query parameter.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    /// Wrapping add of each octet, with no carry between them.
+    Octet,
+    /// Add the whole address as one 32 or 128-bit integer, with carry.
+    Add,
+    /// Bitwise XOR.
+    Xor,
+    /// Rotate left by the key modulo the address width.
+    Rotate,
+    /// A keyed Feistel permutation of the address bits.
+    Permute,
+}
+
+impl Mode {
+    pub fn cipher<A: Address>(self) -> &'static dyn IpCipher<A> {
+        match self {
+            Mode::Octet => &OctetAdd,
+            Mode::Add => &ModularAdd,
+            Mode::Xor => &Xor,
+            Mode::Rotate => &Rotate,
+            Mode::Permute => &Feistel,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Mode::Octet => "octet",
+            Mode::Add => "add",
+            Mode::Xor => "xor",
+            Mode::Rotate => "rotate",
+            Mode::Permute => "permute",
+        }
+    }
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Mode {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "octet" => Ok(Mode::Octet),
+            "add" => Ok(Mode::Add),
+            "xor" => Ok(Mode::Xor),
+            "rotate" => Ok(Mode::Rotate),
+            "permute" => Ok(Mode::Permute),
+            _ => Err(format!(
+                "unknown mode `{}`, expected one of octet, add, xor, rotate, permute",
+                s
+            )),
+        }
+    }
+}
+
+/// All ones across an address of type `A`.
+fn mask<A: Address>() -> u128 {
+    u128::MAX >> (128 - A::BITS)
+}
+
+pub struct OctetAdd;
+
+impl OctetAdd {
+    fn octet_wise<A: Address>(a: A, b: A, op: fn(u8, u8) -> u8) -> A {
+        let (a, b) = (a.to_bits(), b.to_bits());
+        let bits = (0..A::BITS)
+            .step_by(8)
+            .map(|shift| u128::from(op((a >> shift) as u8, (b >> shift) as u8)) << shift)
+            .fold(0, |bits, octet| bits | octet);
+        A::from_bits(bits)
+    }
+}
+
+impl<A: Address> IpCipher<A> for OctetAdd {
+    fn encrypt(&self, address: A, key: A) -> A {
+        Self::octet_wise(address, key, u8::wrapping_add)
+    }
+
+    fn decrypt(&self, address: A, key: A) -> A {
+        Self::octet_wise(address, key, u8::wrapping_sub)
+    }
+
+    fn recover_key(&self, from: A, to: A) -> Option<A> {
+        Some(Self::octet_wise(to, from, u8::wrapping_sub))
+    }
+
+    fn lane_wise(&self) -> bool {
+        true
+    }
+}
+
+pub struct ModularAdd;
+
+impl<A: Address> IpCipher<A> for ModularAdd {
+    fn encrypt(&self, address: A, key: A) -> A {
+        A::from_bits(address.to_bits().wrapping_add(key.to_bits()) & mask::<A>())
+    }
+
+    fn decrypt(&self, address: A, key: A) -> A {
+        A::from_bits(address.to_bits().wrapping_sub(key.to_bits()) & mask::<A>())
+    }
+
+    fn recover_key(&self, from: A, to: A) -> Option<A> {
+        Some(self.decrypt(to, from))
+    }
+}
+
+pub struct Xor;
+
+impl<A: Address> IpCipher<A> for Xor {
+    fn encrypt(&self, address: A, key: A) -> A {
+        A::from_bits(address.to_bits() ^ key.to_bits())
+    }
+
+    fn decrypt(&self, address: A, key: A) -> A {
+        self.encrypt(address, key)
+    }
+
+    fn recover_key(&self, from: A, to: A) -> Option<A> {
+        Some(self.encrypt(from, to))
+    }
+
+    fn lane_wise(&self) -> bool {
+        true
+    }
+}
+
+pub struct Rotate;
+
+impl Rotate {
+    fn rotate_left<A: Address>(bits: u128, by: u32) -> u128 {
+        if by == 0 {
+            return bits;
+        }
+        (bits << by | bits >> (A::BITS - by)) & mask::<A>()
+    }
+
+    fn amount<A: Address>(key: A) -> u32 {
+        (key.to_bits() % u128::from(A::BITS)) as u32
+    }
+}
+
+impl<A: Address> IpCipher<A> for Rotate {
+    fn encrypt(&self, address: A, key: A) -> A {
+        A::from_bits(Self::rotate_left::<A>(address.to_bits(), Self::amount(key)))
+    }
+
+    fn decrypt(&self, address: A, key: A) -> A {
+        let by = (A::BITS - Self::amount(key)) % A::BITS;
+        A::from_bits(Self::rotate_left::<A>(address.to_bits(), by))
+    }
+}
+
+/// A balanced Feistel network over the two halves of the address. Each round
+/// key and round function is derived from the key with SplitMix64, which is
+/// enough to scramble addresses but is not cryptographically strong.
+pub struct Feistel;
+
+impl Feistel {
+    const ROUNDS: u64 = 8;
+
+    fn mix(mut z: u64) -> u64 {
+        z = z.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn round<A: Address>(half: u128, key: u128, round: u64) -> u128 {
+        let half_mask = mask::<A>() >> (A::BITS / 2);
+        let round_key = Self::mix(key as u64 ^ Self::mix((key >> 64) as u64 ^ round));
+        u128::from(Self::mix(half as u64 ^ round_key)) & half_mask
+    }
+
+    fn halves<A: Address>(address: A) -> (u128, u128) {
+        let bits = address.to_bits();
+        let half = A::BITS / 2;
+        (bits >> half, bits & (mask::<A>() >> half))
+    }
+
+    fn join<A: Address>(left: u128, right: u128) -> A {
+        A::from_bits(left << (A::BITS / 2) | right)
+    }
+}
+
+impl<A: Address> IpCipher<A> for Feistel {
+    fn encrypt(&self, address: A, key: A) -> A {
+        let (mut left, mut right) = Self::halves(address);
+        for round in 0..Self::ROUNDS {
+            (left, right) = (right, left ^ Self::round::<A>(right, key.to_bits(), round));
+        }
+        Self::join(left, right)
+    }
+
+    fn decrypt(&self, address: A, key: A) -> A {
+        let (mut left, mut right) = Self::halves(address);
+        for round in (0..Self::ROUNDS).rev() {
+            (left, right) = (right ^ Self::round::<A>(left, key.to_bits(), round), left);
+        }
+        Self::join(left, right)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenges::challenge2::{Ipv4, Ipv6};
+    use proptest::prelude::*;
+
+    const MODES: [Mode; 5] = [
+        Mode::Octet,
+        Mode::Add,
+        Mode::Xor,
+        Mode::Rotate,
+        Mode::Permute,
+    ];
+
+    fn ipv4() -> impl Strategy<Value = Ipv4> {
+        any::<u32>().prop_map(|bits| Ipv4::from_bits(bits.into()))
+    }
+
+    fn ipv6() -> impl Strategy<Value = Ipv6> {
+        any::<u128>().prop_map(Ipv6::from_bits)
+    }
+
+    fn mode() -> impl Strategy<Value = Mode> {
+        prop::sample::select(&MODES[..])
+    }
+
+    fn round_trips<A: Address + PartialEq + fmt::Debug>(
+        mode: Mode,
+        address: A,
+        key: A,
+    ) -> Result<(), TestCaseError> {
+        let cipher = mode.cipher::<A>();
+        let encrypted = cipher.encrypt(address, key);
+        prop_assert_eq!(cipher.decrypt(encrypted, key), address);
+        prop_assert_eq!(cipher.encrypt(cipher.decrypt(address, key), key), address);
+        if let Some(recovered) = cipher.recover_key(address, encrypted) {
+            prop_assert_eq!(recovered, key);
+        }
+        Ok(())
+    }
+
+    proptest! {
+        #[test]
+        fn ipv4_ciphers_round_trip(mode in mode(), address in ipv4(), key in ipv4()) {
+            round_trips(mode, address, key)?;
+        }
+
+        #[test]
+        fn ipv6_ciphers_round_trip(mode in mode(), address in ipv6(), key in ipv6()) {
+            round_trips(mode, address, key)?;
+        }
+    }
+
+    #[test]
+    fn octet_mode_matches_the_original_ipv4_arithmetic() {
+        let (from, key) = (Ipv4::new(10, 0, 0, 0), Ipv4::new(1, 2, 3, 255));
+        let dest = Mode::Octet.cipher().encrypt(from, key);
+        assert_eq!(dest, from.add(&key));
+        assert_eq!(
+            Mode::Octet.cipher().recover_key(from, dest),
+            Some(dest.sub(&from))
+        );
+    }
+
+    #[test]
+    fn add_mode_carries() {
+        let cipher = Mode::Add.cipher::<Ipv4>();
+        let sum = cipher.encrypt(Ipv4::new(10, 0, 0, 255), Ipv4::new(0, 0, 0, 1));
+        assert_eq!(sum, Ipv4::new(10, 0, 1, 0));
+        let wrapped = cipher.encrypt(Ipv4::new(255, 255, 255, 255), Ipv4::new(0, 0, 0, 2));
+        assert_eq!(wrapped, Ipv4::new(0, 0, 0, 1));
+    }
+
+    #[test]
+    fn rotate_mode_moves_bits() {
+        let cipher = Mode::Rotate.cipher::<Ipv4>();
+        let rotated = cipher.encrypt(Ipv4::new(128, 0, 0, 1), Ipv4::new(0, 0, 0, 33));
+        assert_eq!(rotated, Ipv4::new(0, 0, 0, 3));
+    }
+}
@@ -0,0 +1,164 @@
+use axum::{
+    extract::Query,
+    response::{IntoResponse, Response},
+};
+use serde::Deserialize;
+
+use super::{output_format, parse_block, required, AppError, Ipv6, Output};
+
+/// Prefix length SLAAC addresses are formed in.
+const SLAAC_PREFIX: u32 = 64;
+
+#[derive(Deserialize)]
+pub(super) struct MacToAddress {
+    mac: Option<String>,
+    prefix: Option<String>,
+    format: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct AddressToMac {
+    address: Option<String>,
+}
+
+/// Parse a MAC written as `00:1b:63:84:45:e6`, `00-1B-63-84-45-E6`,
+/// `001b.6384.45e6` or `001b638445e6`.
+fn parse_mac(value: &str) -> Result<[u8; 6], String> {
+    if !value.is_ascii() {
+        return Err("expected six hex octets".to_string());
+    }
+    let digits: String = match value.len() {
+        12 => value.to_string(),
+        14 if value.split('.').all(|group| group.len() == 4) => value.replace('.', ""),
+        17 if value.split([':', '-']).all(|group| group.len() == 2) => {
+            let separator = &value[2..3];
+            if value.matches(separator).count() != 5 {
+                return Err("mixed separators".to_string());
+            }
+            value.replace(separator, "")
+        }
+        _ => return Err("expected six hex octets".to_string()),
+    };
+    if digits.len() != 12 || !digits.bytes().all(|byte| byte.is_ascii_hexdigit()) {
+        return Err("expected six hex octets".to_string());
+    }
+    let mut mac = [0; 6];
+    for (i, octet) in mac.iter_mut().enumerate() {
+        *octet = u8::from_str_radix(&digits[2 * i..2 * i + 2], 16).expect("hex digits");
+    }
+    Ok(mac)
+}
+
+fn format_mac(mac: [u8; 6]) -> String {
+    mac.iter()
+        .map(|octet| format!("{:02x}", octet))
+        .collect::<Vec<_>>()
+        .join(":")
+}
+
+/// `GET /2/v6/eui64?mac=..&prefix=2001:db8::/64`: the SLAAC address the
+/// interface with that MAC takes in the /64.
+pub(super) async fn slaac_address(Query(query): Query<MacToAddress>) -> Result<Response, AppError> {
+    let (_, mac) = required("mac", &query.mac)?;
+    let mac = parse_mac(mac).map_err(|reason| AppError::InvalidMac {
+        value: mac.to_string(),
+        reason,
+    })?;
+    let (param, prefix) = required("prefix", &query.prefix)?;
+    let (block, _) = parse_block::<Ipv6>((param, prefix))?;
+    if block.prefix() != SLAAC_PREFIX {
+        return Err(AppError::InvalidAddress {
+            param,
+            value: prefix.to_string(),
+            reason: format!("SLAAC needs a /{} prefix", SLAAC_PREFIX),
+        });
+    }
+    let output = Output {
+        format: output_format(&query.format)?,
+        mapped: false,
+    };
+    let address = Ipv6::from_eui64(&block.network(), mac);
+    Ok(output.address(address).into_response())
+}
+
+/// `GET /2/v6/mac?address=..`: the MAC a SLAAC address was built from.
+pub(super) async fn recover_mac(Query(query): Query<AddressToMac>) -> Result<Response, AppError> {
+    let (param, value) = required("address", &query.address)?;
+    let (block, _) = parse_block::<Ipv6>((param, value))?;
+    if !block.is_single() {
+        return Err(AppError::InvalidAddress {
+            param,
+            value: value.to_string(),
+            reason: "expected a single address".to_string(),
+        });
+    }
+    let mac = block
+        .network()
+        .eui64_mac()
+        .ok_or_else(|| AppError::NotEui64(value.to_string()))?;
+    Ok(format_mac(mac).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::challenges::challenge2::Address;
+
+    fn ipv6(s: &str) -> Ipv6 {
+        Ipv6::parse(s).unwrap()
+    }
+
+    #[test]
+    fn rfc_2464_example() {
+        // RFC 2464, section 4: 34-56-78-9A-BC-DE has the interface identifier
+        // 36-56-78-FF-FE-9A-BC-DE.
+        let mac = parse_mac("34-56-78-9A-BC-DE").unwrap();
+        let address = Ipv6::from_eui64(&ipv6("fe80::"), mac);
+        assert_eq!(address, ipv6("fe80::3656:78ff:fe9a:bcde"));
+        assert_eq!(address.eui64_mac(), Some(mac));
+    }
+
+    #[test]
+    fn prefix_host_bits_are_replaced() {
+        let mac = parse_mac("00:1b:63:84:45:e6").unwrap();
+        let address = Ipv6::from_eui64(&ipv6("2001:db8:1:2:ffff::1"), mac);
+        assert_eq!(address, ipv6("2001:db8:1:2:21b:63ff:fe84:45e6"));
+    }
+
+    #[test]
+    fn locally_administered_bit_is_inverted_both_ways() {
+        let mac = parse_mac("02:00:5e:10:00:01").unwrap();
+        let address = Ipv6::from_eui64(&ipv6("fe80::"), mac);
+        assert_eq!(address, ipv6("fe80::5eff:fe10:1"));
+        assert_eq!(
+            address.eui64_mac().map(format_mac).unwrap(),
+            "02:00:5e:10:00:01"
+        );
+    }
+
+    #[test]
+    fn other_identifiers_have_no_mac() {
+        assert_eq!(ipv6("2001:db8::1").eui64_mac(), None);
+    }
+
+    #[test]
+    fn mac_notations() {
+        let expected = [0x00, 0x1b, 0x63, 0x84, 0x45, 0xe6];
+        for notation in [
+            "00:1b:63:84:45:e6",
+            "00-1B-63-84-45-E6",
+            "001b.6384.45e6",
+            "001b638445e6",
+        ] {
+            assert_eq!(parse_mac(notation), Ok(expected), "{}", notation);
+        }
+        for invalid in [
+            "00:1b:63:84:45",
+            "00:1b-63:84:45:e6",
+            "00:1b:63:84:45:g6",
+            "",
+        ] {
+            assert!(parse_mac(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use std::{
+    net::{Ipv4Addr, Ipv6Addr},
+    str::FromStr,
+};
+
+use super::cidr::{Address, Cidr};
+
+/// How IPv6 results are written, chosen by the `format` query parameter.
+/// IPv4 results are always dot-decimal.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Format {
+    /// RFC 5952 text, e.g. `2001:db8::1`.
+    #[default]
+    Compressed,
+    /// All eight groups in four hex digits, e.g.
+    /// `2001:0db8:0000:0000:0000:0000:0000:0001`.
+    Expanded,
+    /// The last 32 bits as dot-decimal, e.g. `64:ff9b::192.0.2.33`.
+    Embedded,
+}
+
+impl Format {
+    pub fn write(&self, address: Ipv6Addr) -> String {
+        match self {
+            Format::Compressed => address.to_string(),
+            Format::Expanded => address
+                .segments()
+                .iter()
+                .map(|segment| format!("{:04x}", segment))
+                .collect::<Vec<_>>()
+                .join(":"),
+            Format::Embedded => {
+                let segments = address.segments();
+                let octets = address.octets();
+                let tail = Ipv4Addr::new(octets[12], octets[13], octets[14], octets[15]);
+                let head = compress(&segments[..6]);
+                if head.ends_with("::") {
+                    format!("{}{}", head, tail)
+                } else {
+                    format!("{}:{}", head, tail)
+                }
+            }
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "compressed" => Ok(Format::Compressed),
+            "expanded" => Ok(Format::Expanded),
+            "embedded" => Ok(Format::Embedded),
+            _ => Err(format!(
+                "unknown format `{}`, expected one of compressed, expanded, embedded",
+                s
+            )),
+        }
+    }
+}
+
+/// Write groups in hex, replacing the first longest run of two or more zero
+/// groups with `::` as RFC 5952 asks.
+fn compress(segments: &[u16]) -> String {
+    let mut longest = 0..0;
+    let mut start = 0;
+    for (i, segment) in segments.iter().enumerate() {
+        if *segment != 0 {
+            start = i + 1;
+        } else if i + 1 - start > longest.len() {
+            longest = start..i + 1;
+        }
+    }
+    let hex = |groups: &[u16]| {
+        groups
+            .iter()
+            .map(|group| format!("{:x}", group))
+            .collect::<Vec<_>>()
+            .join(":")
+    };
+    if longest.len() < 2 {
+        return hex(segments);
+    }
+    format!(
+        "{}::{}",
+        hex(&segments[..longest.start]),
+        hex(&segments[longest.end..])
+    )
+}
+
+/// How results are written back: IPv4 stays dot-decimal unless the caller
+/// wrote it IPv4-mapped, in which case results are mapped too.
+#[derive(Debug, Clone, Copy)]
+pub struct Output {
+    pub format: Format,
+    pub mapped: bool,
+}
+
+impl Output {
+    fn as_ipv6<A: Address>(&self) -> bool {
+        A::BITS == 128 || self.mapped
+    }
+
+    pub fn address<A: Address>(&self, address: A) -> String {
+        if self.as_ipv6::<A>() {
+            self.format.write(address.to_ipv6())
+        } else {
+            address.to_string()
+        }
+    }
+
+    /// An address with an optional port, as `1.2.3.4:80` or `[fe80::1]:443`.
+    pub fn socket<A: Address>(&self, address: A, port: Option<u16>) -> String {
+        match port {
+            Some(port) if self.as_ipv6::<A>() => format!("[{}]:{}", self.address(address), port),
+            Some(port) => format!("{}:{}", self.address(address), port),
+            None => self.address(address),
+        }
+    }
+
+    pub fn block<A: Address>(&self, block: Cidr<A>) -> String {
+        let mut prefix = block.prefix();
+        if self.as_ipv6::<A>() {
+            prefix += 128 - A::BITS;
+        }
+        format!("{}/{}", self.address(block.network()), prefix)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(format: Format, address: &str) -> String {
+        format.write(address.parse().unwrap())
+    }
+
+    #[test]
+    fn expanded_form_has_every_digit() {
+        assert_eq!(
+            write(Format::Expanded, "2001:db8::1"),
+            "2001:0db8:0000:0000:0000:0000:0000:0001"
+        );
+        assert_eq!(
+            write(Format::Expanded, "::"),
+            "0000:0000:0000:0000:0000:0000:0000:0000"
+        );
+    }
+
+    #[test]
+    fn embedded_form_writes_the_last_32_bits_dotted() {
+        assert_eq!(
+            write(Format::Embedded, "64:ff9b::c000:221"),
+            "64:ff9b::192.0.2.33"
+        );
+        assert_eq!(
+            write(Format::Embedded, "2001:db8:c000:221::"),
+            "2001:db8:c000:221::0.0.0.0"
+        );
+        assert_eq!(write(Format::Embedded, "::ffff:102:304"), "::ffff:1.2.3.4");
+        assert_eq!(write(Format::Embedded, "::1"), "::0.0.0.1");
+        assert_eq!(
+            write(Format::Embedded, "1:0:1:0:1:0:1:1"),
+            "1:0:1:0:1:0:0.1.0.1"
+        );
+    }
+
+    #[test]
+    fn compression_picks_the_first_longest_run() {
+        assert_eq!(compress(&[1, 0, 0, 2, 0, 0]), "1::2:0:0");
+        assert_eq!(compress(&[1, 0, 2, 0, 0, 0]), "1:0:2::");
+    }
+}
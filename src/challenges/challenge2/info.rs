@@ -0,0 +1,253 @@
+use axum::{
+    extract::Query,
+    response::{IntoResponse, Response},
+    Json,
+};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, Ipv6Addr};
+
+use super::{output_format, parse_block, required, Address, AppError, Format, Ipv4, Ipv6, Output};
+
+#[derive(Deserialize)]
+pub(super) struct InfoQuery {
+    address: Option<String>,
+    format: Option<String>,
+}
+
+/// What kind of address an address is, most specific first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+enum Class {
+    Unspecified,
+    Loopback,
+    LinkLocal,
+    Multicast,
+    Documentation,
+    /// RFC 1918 and shared (RFC 6598) IPv4, or IPv6 unique local addresses.
+    Private,
+    Global,
+}
+
+impl Class {
+    /// Classify an address, reading IPv4-mapped addresses as IPv4.
+    fn of(address: Ipv6Addr) -> Self {
+        if let Some(address) = address.to_ipv4_mapped() {
+            let [a, b, ..] = address.octets();
+            return if address.is_unspecified() {
+                Class::Unspecified
+            } else if address.is_loopback() {
+                Class::Loopback
+            } else if address.is_link_local() {
+                Class::LinkLocal
+            } else if address.is_multicast() {
+                Class::Multicast
+            } else if address.is_documentation() {
+                Class::Documentation
+            } else if address.is_private() || (a == 100 && b & 0xc0 == 64) {
+                Class::Private
+            } else {
+                Class::Global
+            };
+        }
+        let [first, second, ..] = address.segments();
+        if address.is_unspecified() {
+            Class::Unspecified
+        } else if address.is_loopback() {
+            Class::Loopback
+        } else if first & 0xffc0 == 0xfe80 {
+            Class::LinkLocal
+        } else if address.is_multicast() {
+            Class::Multicast
+        } else if (first, second) == (0x2001, 0x0db8) || (first == 0x3fff && second & 0xf000 == 0) {
+            Class::Documentation
+        } else if first & 0xfe00 == 0xfc00 {
+            Class::Private
+        } else {
+            Class::Global
+        }
+    }
+}
+
+/// Everything `/2/info` reports about an address and its subnet.
+#[derive(Debug, Serialize)]
+struct SubnetInfo {
+    address: String,
+    family: &'static str,
+    prefix: u32,
+    netmask: String,
+    network: String,
+    /// IPv4 only, and not for /31 and /32, which have no broadcast address.
+    broadcast: Option<String>,
+    first_host: String,
+    last_host: String,
+    /// A decimal string, since an IPv6 subnet may hold up to 2^128 hosts.
+    host_count: String,
+    previous_subnet: Option<String>,
+    next_subnet: Option<String>,
+    class: Class,
+}
+
+fn describe<A: Address>(value: &str, format: Format) -> Result<SubnetInfo, AppError> {
+    let (block, mapped) = parse_block::<A>(("address", value))?;
+    let host = value.split_once('/').map_or(value, |(host, _)| host);
+    let address = parse_block::<A>(("address", host))?.0.network();
+    let output = Output { format, mapped };
+
+    // IPv4 subnets lose their network and broadcast addresses to hosts, except
+    // point-to-point /31s (RFC 3021). IPv6 has no broadcast address.
+    let has_broadcast = A::BITS == 32 && block.host_bits() >= 2;
+    let (first_host, last_host) = if has_broadcast {
+        (
+            A::from_bits(block.network().to_bits() + 1),
+            A::from_bits(block.last().to_bits() - 1),
+        )
+    } else {
+        (block.network(), block.last())
+    };
+    let host_count = match block.host_bits() {
+        128 => "340282366920938463463374607431768211456".to_string(),
+        bits => ((1u128 << bits) - if has_broadcast { 2 } else { 0 }).to_string(),
+    };
+    let (prefix, netmask) = match mapped {
+        true => (
+            block.prefix() + 96,
+            format.write(Ipv6Addr::from_bits(u128::MAX << block.host_bits())),
+        ),
+        false => (block.prefix(), output.address(block.netmask())),
+    };
+
+    Ok(SubnetInfo {
+        address: output.address(address),
+        family: if mapped { Ipv6::FAMILY } else { A::FAMILY },
+        prefix,
+        netmask,
+        network: output.address(block.network()),
+        broadcast: has_broadcast.then(|| output.address(block.last())),
+        first_host: output.address(first_host),
+        last_host: output.address(last_host),
+        host_count,
+        previous_subnet: block.previous().map(|block| output.block(block)),
+        next_subnet: block.next().map(|block| output.block(block)),
+        class: Class::of(address.to_ipv6()),
+    })
+}
+
+/// `GET /2/info?address=10.0.0.5/24`: the subnet an address belongs to and
+/// what kind of address it is. A bare address is its own /32 or /128.
+pub(super) async fn subnet_info(Query(query): Query<InfoQuery>) -> Result<Response, AppError> {
+    let (_, value) = required("address", &query.address)?;
+    let format = output_format(&query.format)?;
+    let host = value.split_once('/').map_or(value, |(host, _)| host);
+    let info = match host.parse::<IpAddr>() {
+        Ok(IpAddr::V6(address)) if address.to_ipv4_mapped().is_none() => {
+            describe::<Ipv6>(value, format)?
+        }
+        _ => describe::<Ipv4>(value, format)?,
+    };
+    Ok(Json(info).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn describe_str(value: &str) -> SubnetInfo {
+        match value.contains(':') && !value.contains("ffff:") {
+            true => describe::<Ipv6>(value, Format::default()).unwrap(),
+            false => describe::<Ipv4>(value, Format::default()).unwrap(),
+        }
+    }
+
+    #[test]
+    fn ipv4_subnet() {
+        let info = describe_str("10.0.0.5/24");
+        assert_eq!(info.address, "10.0.0.5");
+        assert_eq!(info.netmask, "255.255.255.0");
+        assert_eq!(info.network, "10.0.0.0");
+        assert_eq!(info.broadcast.as_deref(), Some("10.0.0.255"));
+        assert_eq!(info.first_host, "10.0.0.1");
+        assert_eq!(info.last_host, "10.0.0.254");
+        assert_eq!(info.host_count, "254");
+        assert_eq!(info.previous_subnet.as_deref(), Some("9.255.255.0/24"));
+        assert_eq!(info.next_subnet.as_deref(), Some("10.0.1.0/24"));
+        assert_eq!(info.class, Class::Private);
+    }
+
+    #[test]
+    fn point_to_point_and_host_routes_have_no_broadcast() {
+        let pair = describe_str("192.0.2.7/31");
+        assert_eq!(pair.broadcast, None);
+        assert_eq!(
+            (pair.first_host.as_str(), pair.last_host.as_str()),
+            ("192.0.2.6", "192.0.2.7")
+        );
+        assert_eq!(pair.host_count, "2");
+
+        let host = describe_str("255.255.255.255");
+        assert_eq!(host.host_count, "1");
+        assert_eq!(host.next_subnet, None);
+        assert_eq!(host.previous_subnet.as_deref(), Some("255.255.255.254/32"));
+    }
+
+    #[test]
+    fn ipv6_subnet() {
+        let info = describe_str("2001:db8::1/64");
+        assert_eq!(info.netmask, "ffff:ffff:ffff:ffff::");
+        assert_eq!(info.network, "2001:db8::");
+        assert_eq!(info.broadcast, None);
+        assert_eq!(info.last_host, "2001:db8::ffff:ffff:ffff:ffff");
+        assert_eq!(info.host_count, "18446744073709551616");
+        assert_eq!(info.next_subnet.as_deref(), Some("2001:db8:0:1::/64"));
+        assert_eq!(info.class, Class::Documentation);
+
+        let everything = describe_str("::/0");
+        assert_eq!(
+            everything.host_count,
+            "340282366920938463463374607431768211456"
+        );
+        assert_eq!(everything.previous_subnet, None);
+        assert_eq!(everything.next_subnet, None);
+    }
+
+    #[test]
+    fn mapped_addresses_are_described_as_mapped_ipv4() {
+        let info = describe_str("::ffff:192.168.1.9/120");
+        assert_eq!(info.family, "IPv6");
+        assert_eq!(info.prefix, 120);
+        assert_eq!(info.netmask, "ffff:ffff:ffff:ffff:ffff:ffff:ffff:ff00");
+        assert_eq!(info.network, "::ffff:192.168.1.0");
+        assert_eq!(info.host_count, "254");
+        assert_eq!(info.class, Class::Private);
+    }
+
+    #[test]
+    fn classification() {
+        let cases = [
+            ("0.0.0.0", Class::Unspecified),
+            ("127.0.0.1", Class::Loopback),
+            ("169.254.1.1", Class::LinkLocal),
+            ("224.0.0.251", Class::Multicast),
+            ("198.51.100.7", Class::Documentation),
+            ("172.16.0.1", Class::Private),
+            ("100.64.0.1", Class::Private),
+            ("8.8.8.8", Class::Global),
+            ("::", Class::Unspecified),
+            ("::1", Class::Loopback),
+            ("fe80::1", Class::LinkLocal),
+            ("ff02::1", Class::Multicast),
+            ("3fff::1", Class::Documentation),
+            ("3fff:fff::1", Class::Documentation),
+            ("3fff:1000::1", Class::Global),
+            ("3ff0::1", Class::Global),
+            ("fd00::1", Class::Private),
+            ("2606:4700::1111", Class::Global),
+        ];
+        for (address, class) in cases {
+            let address = match address.parse().unwrap() {
+                IpAddr::V4(address) => address.to_ipv6_mapped(),
+                IpAddr::V6(address) => address,
+            };
+            assert_eq!(Class::of(address), class, "{}", address);
+        }
+    }
+}
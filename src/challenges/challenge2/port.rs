@@ -0,0 +1,129 @@
+use super::{AppError, Mode};
+
+/// Which way a handler runs its cipher, and so which port operation matches.
+#[derive(Debug, Clone, Copy)]
+pub enum Direction {
+    Encrypt,
+    Decrypt,
+    RecoverKey,
+}
+
+/// What happens to the ports of socket addresses, chosen by the `port` query
+/// parameter: `transform` (the default) or `keep`.
+#[derive(Debug, Clone, Copy)]
+pub enum Ports {
+    /// The result keeps the port of the address being transformed.
+    Keep,
+    /// The result has no port, as for recovered keys when ports are kept.
+    Discard,
+    /// The ports go through the 16-bit counterpart of the address cipher.
+    Transform(fn(u16, u16) -> u16),
+    /// The mode has no port counterpart.
+    Unsupported(Mode),
+}
+
+impl Ports {
+    pub fn new(port: &Option<String>, mode: Mode, direction: Direction) -> Result<Self, AppError> {
+        match port.as_deref() {
+            None | Some("transform") => {}
+            Some("keep") => {
+                return Ok(match direction {
+                    Direction::RecoverKey => Ports::Discard,
+                    _ => Ports::Keep,
+                })
+            }
+            Some(other) => return Err(AppError::InvalidPortMode(other.to_string())),
+        }
+        let op: Option<fn(u16, u16) -> u16> = match (mode, direction) {
+            (Mode::Octet | Mode::Add, Direction::Encrypt) => Some(u16::wrapping_add),
+            (Mode::Octet | Mode::Add, Direction::Decrypt) => Some(u16::wrapping_sub),
+            (Mode::Octet | Mode::Add, Direction::RecoverKey) => {
+                Some(|from, to| to.wrapping_sub(from))
+            }
+            (Mode::Xor, _) => Some(|a, b| a ^ b),
+            (Mode::Rotate, Direction::Encrypt) => {
+                Some(|port, key| port.rotate_left(u32::from(key % 16)))
+            }
+            (Mode::Rotate, Direction::Decrypt) => {
+                Some(|port, key| port.rotate_right(u32::from(key % 16)))
+            }
+            _ => None,
+        };
+        Ok(op.map_or(Ports::Unsupported(mode), Ports::Transform))
+    }
+
+    /// The port of the result, given the ports of both operands. A missing
+    /// port counts as 0; the result has a port only if an operand had one.
+    pub fn apply(self, left: Option<u16>, right: Option<u16>) -> Result<Option<u16>, AppError> {
+        if left.is_none() && right.is_none() {
+            return Ok(None);
+        }
+        match self {
+            Ports::Keep => Ok(left),
+            Ports::Discard => Ok(None),
+            Ports::Transform(op) => Ok(Some(op(left.unwrap_or(0), right.unwrap_or(0)))),
+            Ports::Unsupported(mode) => Err(AppError::NoPortTransform(mode)),
+        }
+    }
+}
+
+/// Split a socket address such as `1.2.3.4:8080` or `[fe80::1]:443` into its
+/// address and port. Anything else is returned whole, without a port.
+pub fn split_port(value: &str) -> Result<(&str, Option<u16>), String> {
+    let (address, port) = match value.strip_prefix('[') {
+        Some(rest) => {
+            let (address, rest) = rest.split_once(']').ok_or("missing `]`")?;
+            match rest.strip_prefix(':') {
+                Some(port) => (address, port),
+                None if rest.is_empty() => return Ok((address, None)),
+                None => return Err(format!("unexpected `{}` after `]`", rest)),
+            }
+        }
+        // IPv6 addresses have at least two colons, so one colon is a port.
+        None => match value.split_once(':') {
+            Some((address, port)) if !port.contains(':') => (address, port),
+            _ => return Ok((value, None)),
+        },
+    };
+    let port = port
+        .parse()
+        .map_err(|_| format!("invalid port `{}`", port))?;
+    Ok((address, Some(port)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn socket_addresses_split() {
+        assert_eq!(split_port("1.2.3.4:8080"), Ok(("1.2.3.4", Some(8080))));
+        assert_eq!(split_port("[fe80::1]:443"), Ok(("fe80::1", Some(443))));
+        assert_eq!(split_port("[fe80::1]"), Ok(("fe80::1", None)));
+        assert_eq!(split_port("fe80::1"), Ok(("fe80::1", None)));
+        assert_eq!(split_port("10.0.0.0/8"), Ok(("10.0.0.0/8", None)));
+        assert!(split_port("1.2.3.4:65536").is_err());
+        assert!(split_port("[fe80::1]443").is_err());
+        assert!(split_port("[fe80::1:443").is_err());
+    }
+
+    #[test]
+    fn ports_follow_the_mode() {
+        let add = Ports::new(&None, Mode::Octet, Direction::Encrypt).unwrap();
+        assert_eq!(add.apply(Some(65535), Some(2)).unwrap(), Some(1));
+        let sub = Ports::new(&None, Mode::Octet, Direction::RecoverKey).unwrap();
+        assert_eq!(sub.apply(Some(8080), Some(1)).unwrap(), Some(57457));
+        let xor = Ports::new(&None, Mode::Xor, Direction::Decrypt).unwrap();
+        assert_eq!(xor.apply(Some(443), None).unwrap(), Some(443));
+
+        let keep = Some("keep".to_string());
+        let kept = Ports::new(&keep, Mode::Xor, Direction::Encrypt).unwrap();
+        assert_eq!(kept.apply(Some(443), Some(1)).unwrap(), Some(443));
+        let key = Ports::new(&keep, Mode::Xor, Direction::RecoverKey).unwrap();
+        assert_eq!(key.apply(Some(443), Some(443)).unwrap(), None);
+
+        let permute = Ports::new(&None, Mode::Permute, Direction::Encrypt).unwrap();
+        assert_eq!(permute.apply(None, None).unwrap(), None);
+        assert!(permute.apply(Some(1), None).is_err());
+    }
+}
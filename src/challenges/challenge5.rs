@@ -1,16 +1,128 @@
 use axum::{
-    http::{HeaderMap, StatusCode},
+    extract::{Query, State},
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap, StatusCode,
+    },
     // TODO: This was a mind bender, need to use Result from
     // axum to be able to return it from handler
     response::{IntoResponse, Response, Result},
-    routing::post,
+    routing::{get, post},
+    Json,
     Router,
 };
 use cargo_manifest::Manifest;
 use serde::{Deserialize, Serialize};
 use serde_yaml;
+use shuttle_runtime::SecretStore;
+use sqlx::PgPool;
+use std::sync::Arc;
 use thiserror::Error;
 
+use archive::{Archive, Submission};
+use policy::{Policies, Violation};
+
+mod archive;
+mod convert;
+mod literals;
+mod orders;
+mod policy;
+mod rust_version;
+mod validate;
+mod workspace;
+
+/// Keyword a manifest must list for its orders to be read.
+const MAGIC_KEYWORD: &str = "Christmas 2024";
+
+#[derive(Debug)]
+struct AppState {
+    policies: Policies,
+    archive: Archive,
+}
+
+/// The manifest formats accepted, by Content-Type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Format {
+    Toml,
+    Yaml,
+    Json,
+}
+
+impl Format {
+    /// The format of a Content-Type, ignoring case and parameters. A
+    /// `charset` other than UTF-8 rules the body out, as it is read as UTF-8.
+    fn from_content_type(content_type: &str) -> Option<Self> {
+        let mut params = content_type.split(';').map(str::trim);
+        let media_type = params.next()?.to_ascii_lowercase();
+        let charset = params.find_map(|param| {
+            let (name, value) = param.split_once('=')?;
+            name.trim()
+                .eq_ignore_ascii_case("charset")
+                .then(|| value.trim().trim_matches('"'))
+        });
+        if charset.is_some_and(|charset| !charset.eq_ignore_ascii_case("utf-8")) {
+            return None;
+        }
+        match media_type.as_str() {
+            "application/toml" => Some(Format::Toml),
+            "application/yaml" => Some(Format::Yaml),
+            "application/json" => Some(Format::Json),
+            _ => None,
+        }
+    }
+
+    fn media_type(&self) -> &'static str {
+        match self {
+            Format::Toml => "application/toml",
+            Format::Yaml => "application/yaml",
+            Format::Json => "application/json",
+        }
+    }
+
+    /// The most preferred format an `Accept` header allows. A wildcard means
+    /// JSON.
+    fn from_accept(accept: &str) -> Option<Self> {
+        preferred(accept, |media_type| match media_type {
+            "*/*" | "application/*" => Some(Format::Json),
+            media_type => Format::from_content_type(media_type),
+        })
+    }
+}
+
+/// The most preferred of the media ranges in an `Accept` header that `pick`
+/// recognises, by q-value and then order.
+fn preferred<T>(accept: &str, pick: impl Fn(&str) -> Option<T>) -> Option<T> {
+    let mut ranges: Vec<(f32, T)> = accept
+        .split(',')
+        .filter_map(|range| {
+            let mut params = range.split(';').map(str::trim);
+            let media_type = params.next()?.to_lowercase();
+            let q = params
+                .find_map(|param| param.strip_prefix("q="))
+                .map_or(Some(1.0), |q| q.parse().ok())?;
+            let picked = pick(&media_type)?;
+            (q > 0.0).then_some((q, picked))
+        })
+        .collect();
+    ranges.sort_by(|a, b| b.0.total_cmp(&a.0));
+    ranges.into_iter().next().map(|(_, picked)| picked)
+}
+
+#[derive(Deserialize)]
+struct ManifestParams {
+    /// Return a report of every issue instead of the orders.
+    #[serde(default)]
+    validate: bool,
+    /// Return quantities summed per item, and the orders skipped, as JSON.
+    #[serde(default)]
+    aggregate: bool,
+    /// Report fractional quantities instead of skipping them.
+    #[serde(default)]
+    fractional: bool,
+    /// Named policy to check the manifest against, instead of the default.
+    policy: Option<String>,
+}
+
 #[derive(Deserialize, Serialize, Debug)]
 struct Package {
     package: PackageInfo,
@@ -18,10 +130,9 @@ struct Package {
 
 #[derive(Deserialize, Serialize, Debug)]
 struct PackageInfo {
+    name: Option<String>,
     metadata: Option<Metadata>,
     keywords: Option<Vec<String>>,
-    #[serde(rename = "rust-version")]
-    rust_version: Option<String>,
 }
 
 #[derive(Deserialize, Serialize, Debug)]
@@ -32,7 +143,9 @@ struct Metadata {
 #[derive(Deserialize, Serialize, Debug)]
 struct Order {
     item: String,
-    quantity: Option<f32>,
+    /// Only checked here; quantities are read from the document by
+    /// [`orders::read_order`].
+    quantity: Option<serde_json::Number>,
     count: Option<u32>,
 }
 
@@ -56,108 +169,371 @@ enum AppError {
     #[error("No Content Returned")]
     NoContent,
 
+    #[error("Invalid document: {0}")]
+    InvalidDocument(String),
+
+    #[error("Cannot convert document: {0}")]
+    Unconvertible(String),
+
+    #[error("Not acceptable")]
+    NotAcceptable,
+
+    #[error("Quantity {quantity} of `{item}` is out of range")]
+    QuantityOutOfRange {
+        item: String,
+        quantity: serde_json::Number,
+    },
+
+    #[error("Total quantity of the orders is out of range")]
+    TotalOutOfRange,
+
     #[error("Invalid Cargo manifest")]
     InvalidManifest,
 
-    #[error("Magic keyword not provided")]
-    MagicKeywordNotFound,
+    #[error("{}", .0.message)]
+    PolicyViolation(Violation),
+
+    #[error("Unknown policy `{0}`")]
+    UnknownPolicy(String),
+
+    #[error("Submission Not Found")]
+    SubmissionNotFound,
+
+    #[error("Invalid ID: {0}")]
+    InvalidId(String),
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+
+    #[error("Invalid multipart upload: {0}")]
+    InvalidUpload(String),
+
+    #[error("Exactly one manifest must have a [workspace] table")]
+    NoWorkspaceRoot,
+
+    #[error("Manifest `{0}` has no [package] table")]
+    NotAPackage(String),
+
+    #[error("`{member}` inherits `{key}`, which [workspace.package] does not set")]
+    MissingInherited { member: String, key: String },
+
+    #[error("`{member}` cannot inherit `{key}` from the workspace")]
+    NotInheritable { member: String, key: String },
+
+    #[error("`{0}` is not a member of the workspace")]
+    NotAMember(String),
+
+    #[error("Workspace member `{0}` was not uploaded")]
+    MissingMember(String),
+
+    #[error("More than one member is named `{0}`")]
+    DuplicatePackage(String),
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
+        let message = self.to_string();
         let (status, error_message) = match self {
             AppError::TomlParseError(_) => (StatusCode::NO_CONTENT, "Failed to parse TOML"),
             AppError::JsonParseError(_) => (StatusCode::NO_CONTENT, "Failed to parse JSON"),
             AppError::YamlParseError(_) => (StatusCode::NO_CONTENT, "Failed to parse YAML"),
             AppError::NoContent => (StatusCode::NO_CONTENT, "No Content Returned"),
-            AppError::MagicKeywordNotFound => {
-                (StatusCode::BAD_REQUEST, "Magic keyword not provided")
+            AppError::PolicyViolation(violation) => {
+                let rule = [("x-policy-rule", violation.rule.code())];
+                return (StatusCode::BAD_REQUEST, rule, violation.message).into_response();
             }
+            AppError::UnknownPolicy(_) => (StatusCode::BAD_REQUEST, message.as_str()),
             AppError::UnsupportedMediaType => {
                 (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Unsupported media type")
             }
             AppError::MissingContentType => (StatusCode::BAD_REQUEST, "Invalid Content-Type"),
             AppError::InvalidManifest => (StatusCode::BAD_REQUEST, "Invalid manifest"),
+            AppError::InvalidDocument(_) => (StatusCode::BAD_REQUEST, message.as_str()),
+            AppError::Unconvertible(_) => (StatusCode::UNPROCESSABLE_ENTITY, message.as_str()),
+            AppError::QuantityOutOfRange { .. } => (StatusCode::BAD_REQUEST, message.as_str()),
+            AppError::TotalOutOfRange => (StatusCode::BAD_REQUEST, message.as_str()),
+            AppError::InvalidUpload(_)
+            | AppError::NoWorkspaceRoot
+            | AppError::NotAPackage(_)
+            | AppError::MissingInherited { .. }
+            | AppError::NotInheritable { .. }
+            | AppError::NotAMember(_)
+            | AppError::MissingMember(_)
+            | AppError::DuplicatePackage(_) => (StatusCode::BAD_REQUEST, message.as_str()),
+            AppError::SubmissionNotFound => (StatusCode::NOT_FOUND, message.as_str()),
+            AppError::InvalidId(_) => (StatusCode::BAD_REQUEST, message.as_str()),
+            AppError::DatabaseError(e) => {
+                eprintln!("Database error: {:?}", e);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Database error")
+            }
+            AppError::NotAcceptable => (
+                StatusCode::NOT_ACCEPTABLE,
+                "Accept must allow application/toml, application/yaml or application/json",
+            ),
         };
 
-        (status, error_message).into_response()
+        (status, error_message.to_string()).into_response()
     }
 }
-async fn extract_toml(headers: HeaderMap, body: String) -> Result<String, AppError> {
+async fn extract_toml(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ManifestParams>,
+    headers: HeaderMap,
+    body: String,
+) -> Result<Response, AppError> {
+    let policy = state
+        .policies
+        .get(params.policy.as_deref())
+        .ok_or_else(|| AppError::UnknownPolicy(params.policy.clone().unwrap_or_default()))?;
     let content_type = headers
-        .get(axum::http::header::CONTENT_TYPE)
-        .ok_or(AppError::MissingContentType)?;
-
-    // Check if the Content-Type is allowed
-    // let allowed_content_type = ["application/toml", "application/yaml", "application/json"];
-    let content_type_str = content_type.to_str().unwrap().to_lowercase();
-    // if !allowed_content_type.contains(&content_type_str.as_str()) {
-    //     return Err(AppError::UnsupportedMediaType);
-    // }
-    if content_type_str == "application/toml" {
+        .get(CONTENT_TYPE)
+        .ok_or(AppError::MissingContentType)?
+        .to_str()
+        .map_err(|_| AppError::UnsupportedMediaType)?;
+    let format = Format::from_content_type(content_type).ok_or(AppError::UnsupportedMediaType)?;
+    if params.validate || params.aggregate {
+        let report = validate::validate(format, &body, policy);
+        if params.validate {
+            return Ok(Json(report).into_response());
+        }
+        if !report.valid {
+            return Ok((StatusCode::BAD_REQUEST, Json(report)).into_response());
+        }
+        let document = validate::parse(format, &body).expect("validated manifest parses");
+        let orders = document
+            .pointer("/package/metadata/orders")
+            .and_then(serde_json::Value::as_array)
+            .map_or(&[][..], Vec::as_slice);
+        let accepted: Vec<_> = orders
+            .iter()
+            .filter_map(|order| {
+                let valid = orders::read_order(order, params.fractional).ok()?;
+                Some(orders::Reported {
+                    member: None,
+                    quantity: orders::read_total(order, valid.quantity).ok()?,
+                    item: valid.item,
+                })
+            })
+            .collect();
+        let submission = Submission {
+            submitter: archive::submitter(&headers),
+            package: document
+                .pointer("/package/name")
+                .and_then(|name| name.as_str())
+                .map(str::to_string),
+            format,
+            body,
+            orders: accepted,
+        };
+        let response = Json(orders::aggregate(orders, params.fractional)).into_response();
+        let id = state.archive.record(submission).await;
+        return Ok(archive::with_submission_id(response, id));
+    }
+    if format == Format::Toml {
         match Manifest::from_slice(body.as_bytes()) {
             Ok(_) => {}
             Err(_e) => return Err(AppError::InvalidManifest),
         }
     }
 
-    let payload: Package = match content_type_str.as_str() {
-        "application/toml" => toml::from_str::<Package>(&body)?,
-        "application/yaml" => serde_yaml::from_str::<Package>(&body).unwrap(),
-        "application/json" => serde_json::from_str::<Package>(&body).unwrap(),
-        _ => return Err(AppError::UnsupportedMediaType),
+    let payload: Package = match format {
+        Format::Toml => toml::from_str::<Package>(&body)?,
+        Format::Yaml => {
+            serde_yaml::from_str::<Package>(&body).map_err(|_| AppError::InvalidManifest)?
+        }
+        Format::Json => {
+            serde_json::from_str::<Package>(&body).map_err(|_| AppError::InvalidManifest)?
+        }
     };
 
-    if !payload
-        .package
-        .keywords
-        .unwrap_or(vec![])
-        .contains(&String::from("Christmas 2024"))
-    {
-        return Err(AppError::MagicKeywordNotFound);
+    let document = validate::parse(format, &body).map_err(|_| AppError::InvalidManifest)?;
+    rust_version::read(&document["package"]).map_err(|_| AppError::InvalidManifest)?;
+    if let Some(violation) = policy.violations(&document["package"]).into_iter().next() {
+        return Err(AppError::PolicyViolation(violation));
     }
-    if content_type_str != "application/toml" {
-        if payload.package.rust_version.is_some()
-            && payload
-                .package
-                .rust_version
-                .unwrap()
-                .parse::<f32>()
-                .is_err()
-        {
-            return Err(AppError::InvalidManifest);
-        };
+    let reported = reported_orders(&document["package"], None, params.fractional)?;
+    if reported.is_empty() {
+        return Err(AppError::NoContent);
     }
-    let response_parts: Vec<String> = payload
-        .package
-        .metadata
-        .unwrap_or(Metadata { orders: vec![] })
-        .orders
-        .iter()
-        .filter_map(|order| {
-            if order.quantity.is_some() {
-                Some(order)
-            } else {
-                None
-            }
-        })
-        .filter_map(|order| {
-            if order.quantity.unwrap().fract() == 0.0 {
-                Some(order)
-            } else {
-                None
+
+    // Only manifests that get a response are archived.
+    let response = respond(&headers, payload.package.name.clone(), reported.clone())?;
+    let submission = Submission {
+        submitter: archive::submitter(&headers),
+        package: payload.package.name,
+        format,
+        body,
+        orders: reported,
+    };
+    let id = state.archive.record(submission).await;
+    Ok(archive::with_submission_id(response, id))
+}
+
+/// Every order of a `[package]` that reads as valid, labelled with `member`. A quantity too large to represent exactly rejects
+/// the whole package.
+fn reported_orders(
+    package: &serde_json::Value,
+    member: Option<&str>,
+    fractional: bool,
+) -> Result<Vec<orders::Reported>, AppError> {
+    let package_orders = package
+        .pointer("/metadata/orders")
+        .and_then(serde_json::Value::as_array)
+        .map_or(&[][..], Vec::as_slice);
+    let mut reported = vec![];
+    for order in package_orders {
+        match orders::read_order(order, fractional) {
+            Ok(valid) => reported.push(orders::Reported {
+                member: member.map(str::to_string),
+                quantity: valid.quantity,
+                item: valid.item,
+            }),
+            Err(rejection) if rejection.out_of_range => {
+                return Err(AppError::QuantityOutOfRange {
+                    item: order["item"].as_str().unwrap_or_default().to_string(),
+                    quantity: order["quantity"].as_number().cloned().unwrap_or(0.into()),
+                })
             }
+            Err(_) => {}
+        }
+    }
+    Ok(reported)
+}
+
+/// The reported orders as plain text, one per line, unless `Accept` prefers
+/// a document format, in which case they are listed with their total.
+fn respond(
+    headers: &HeaderMap,
+    package: Option<String>,
+    reported: Vec<orders::Reported>,
+) -> Result<Response, AppError> {
+    let output = headers
+        .get(ACCEPT)
+        .and_then(|accept| accept.to_str().ok())
+        .and_then(|accept| {
+            preferred(accept, |media_type| match media_type {
+                "text/plain" | "text/*" | "*/*" => Some(None),
+                media_type => Format::from_content_type(media_type).map(Some),
+            })
         })
-        .map(|order| format!("{}: {}", order.item, order.quantity.unwrap()))
-        .collect();
-    if response_parts.is_empty() {
-        return Err(AppError::NoContent);
+        .flatten();
+    if let Some(output) = output {
+        let listing = orders::Listing::new(package, reported).ok_or(AppError::TotalOutOfRange)?;
+        let document = convert::write(output, &listing)?;
+        return Ok(([(CONTENT_TYPE, output.media_type())], document).into_response());
     }
+    let response = reported
+        .iter()
+        .map(|order| match &order.member {
+            Some(member) => format!("{}: {} ({})", order.item, order.quantity, member),
+            None => format!("{}: {}", order.item, order.quantity),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    Ok(response.into_response())
+}
 
-    let response = response_parts.join("\n");
-    Ok(response)
+pub fn router(pool: PgPool, secrets: &SecretStore) -> Router {
+    let state = Arc::new(AppState {
+        policies: Policies::from_secrets(secrets),
+        archive: Archive { pool },
+    });
+    Router::new()
+        .route("/manifest", post(extract_toml))
+        .route("/convert", post(convert::convert))
+        .route("/workspace", post(workspace::workspace))
+        .route("/submissions", get(archive::list_submissions))
+        .route("/submissions/:id", get(archive::get_submission))
+        .route("/orders", get(archive::query_orders))
+        .with_state(state)
 }
 
-pub fn router() -> Router {
-    Router::new().route("/manifest", post(extract_toml))
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn content_type_parameters_are_understood() {
+        for (content_type, format) in [
+            ("application/json", Some(Format::Json)),
+            ("application/json; charset=utf-8", Some(Format::Json)),
+            ("Application/TOML;charset=\"UTF-8\"", Some(Format::Toml)),
+            ("application/yaml; version=1.2", Some(Format::Yaml)),
+            ("application/json; charset=latin1", None),
+            ("text/plain", None),
+        ] {
+            assert_eq!(
+                Format::from_content_type(content_type),
+                format,
+                "{}",
+                content_type
+            );
+        }
+    }
+
+    #[test]
+    fn reported_orders_are_not_counted() {
+        let package = serde_json::json!({"metadata": {"orders": [
+            {"item": "Toy car", "quantity": 2, "count": 3},
+            {"item": "Ribbon", "quantity": 1.5},
+            {"item": "Star", "quantity": "many"},
+        ]}});
+        let quantities = |fractional| {
+            reported_orders(&package, Some("elves"), fractional)
+                .unwrap()
+                .into_iter()
+                .map(|order| (order.member.unwrap(), order.quantity.to_string()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            quantities(false),
+            vec![("elves".to_string(), "2".to_string())]
+        );
+        assert_eq!(quantities(true)[1].1, "1.5");
+
+        let huge = serde_json::json!({"metadata": {"orders": [
+            {"item": "Star", "quantity": 1e29},
+        ]}});
+        assert!(matches!(
+            reported_orders(&huge, None, false),
+            Err(AppError::QuantityOutOfRange { item, .. }) if item == "Star"
+        ));
+    }
+
+    #[tokio::test]
+    async fn listings_are_written_with_numbers_in_every_format() {
+        let reported = vec![orders::Reported {
+            member: None,
+            item: "Ribbon".to_string(),
+            quantity: rust_decimal::Decimal::new(35, 1),
+        }];
+        let listing = |accept: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT, accept.parse().unwrap());
+            let response = respond(&headers, Some("elves".to_string()), reported.clone());
+            async {
+                let body = response.unwrap().into_body();
+                let body = axum::body::to_bytes(body, usize::MAX).await.unwrap();
+                String::from_utf8(body.to_vec()).unwrap()
+            }
+        };
+        assert_eq!(
+            listing("application/toml").await,
+            "package = \"elves\"\ntotal = 3.5\n\n[[orders]]\nitem = \"Ribbon\"\nquantity = 3.5\n"
+        );
+        assert_eq!(
+            listing("application/yaml").await,
+            "package: elves\ntotal: 3.5\norders:\n- item: Ribbon\n  quantity: 3.5\n"
+        );
+    }
+
+    #[test]
+    fn accept_prefers_by_q_value() {
+        assert_eq!(
+            Format::from_accept("application/yaml;q=0.5, application/toml"),
+            Some(Format::Toml)
+        );
+        assert_eq!(Format::from_accept("*/*"), Some(Format::Json));
+        assert_eq!(Format::from_accept("text/html"), None);
+    }
 }
@@ -0,0 +1,478 @@
+use axum::{
+    extract::{Path, Query, State},
+    http::{HeaderMap, HeaderValue},
+    response::{Response, Result},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize, Serializer};
+use sqlx::{FromRow, PgPool};
+use std::{sync::Arc, time::Duration};
+use uuid::Uuid;
+
+use super::{orders::Reported, AppError, AppState, Format};
+
+/// Submissions listed per request unless `?limit=` says otherwise.
+const DEFAULT_LIMIT: i64 = 20;
+
+/// Most submissions listed per request.
+const MAX_LIMIT: i64 = 100;
+
+/// How long a response waits for its submission to be archived. A slower
+/// archive is still written, but the response goes without
+/// `X-Submission-Id`.
+const RECORD_TIMEOUT: Duration = Duration::from_millis(250);
+
+/// Quantities as JSON numbers, as `/5/manifest` reports them.
+fn quantity<S: Serializer>(quantity: &Decimal, serializer: S) -> Result<S::Ok, S::Error> {
+    super::orders::number(*quantity).serialize(serializer)
+}
+
+/// Sums of quantities as JSON numbers. Every quantity fits a [`Decimal`] but
+/// their sum may not, so Postgres sums them as `NUMERIC` and its text is
+/// passed on as it is, less trailing zeros.
+fn sum<S: Serializer>(sum: &str, serializer: S) -> Result<S::Ok, S::Error> {
+    let sum = match sum.contains('.') {
+        true => sum.trim_end_matches('0').trim_end_matches('.'),
+        false => sum,
+    };
+    let sum: serde_json::Number = sum.parse().map_err(serde::ser::Error::custom)?;
+    sum.serialize(serializer)
+}
+
+/// Identify who submitted a manifest by the `X-Submitter` header.
+pub(super) fn submitter(headers: &HeaderMap) -> String {
+    headers
+        .get("x-submitter")
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|submitter| !submitter.is_empty())
+        .unwrap_or("anonymous")
+        .to_string()
+}
+
+/// Point the response of an archived submission at its record.
+pub(super) fn with_submission_id(mut response: Response, id: Option<Uuid>) -> Response {
+    if let Some(id) = id {
+        let id = HeaderValue::from_str(&id.to_string()).expect("UUIDs are valid headers");
+        response.headers_mut().insert("x-submission-id", id);
+    }
+    response
+}
+
+/// An accepted manifest and the orders read from it.
+#[derive(Debug)]
+pub(super) struct Submission {
+    pub(super) submitter: String,
+    pub(super) package: Option<String>,
+    pub(super) format: Format,
+    pub(super) body: String,
+    pub(super) orders: Vec<Reported>,
+}
+
+#[derive(FromRow, Serialize, Debug)]
+pub(super) struct SubmissionSummary {
+    id: Uuid,
+    submitter: String,
+    package: Option<String>,
+    format: String,
+    created_at: DateTime<Utc>,
+    orders: i64,
+    #[serde(serialize_with = "sum")]
+    total: String,
+}
+
+#[derive(FromRow, Serialize, Debug)]
+struct StoredOrder {
+    item: String,
+    #[serde(serialize_with = "quantity")]
+    quantity: Decimal,
+}
+
+#[derive(FromRow, Serialize, Debug)]
+pub(super) struct SubmissionRecord {
+    id: Uuid,
+    submitter: String,
+    package: Option<String>,
+    format: String,
+    created_at: DateTime<Utc>,
+    #[sqlx(rename = "body")]
+    manifest: String,
+    #[sqlx(skip)]
+    orders: Vec<StoredOrder>,
+}
+
+#[derive(FromRow, Serialize, Debug)]
+struct MatchingOrder {
+    submission: Uuid,
+    submitter: String,
+    created_at: DateTime<Utc>,
+    item: String,
+    #[serde(serialize_with = "quantity")]
+    quantity: Decimal,
+}
+
+#[derive(FromRow, Serialize, Debug)]
+struct ItemTotal {
+    /// Items are told apart case-insensitively, and named as first ordered.
+    item: String,
+    orders: i64,
+    #[serde(serialize_with = "sum")]
+    quantity: String,
+}
+
+#[derive(Serialize, Debug)]
+pub(super) struct OrderReport {
+    orders: Vec<MatchingOrder>,
+    totals: Vec<ItemTotal>,
+    #[serde(serialize_with = "sum")]
+    total: String,
+}
+
+#[derive(Deserialize)]
+pub(super) struct ListParams {
+    submitter: Option<String>,
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+#[derive(Deserialize)]
+pub(super) struct OrderParams {
+    /// Item name, compared case-insensitively.
+    item: Option<String>,
+    /// Earliest submission time, inclusive.
+    from: Option<DateTime<Utc>>,
+    /// Latest submission time, exclusive.
+    to: Option<DateTime<Utc>>,
+    /// Orders listed, not totalled: the totals cover every matching order.
+    limit: Option<i64>,
+    offset: Option<i64>,
+}
+
+/// Every accepted manifest with its orders, kept in Postgres so they can be
+/// looked up after the response is sent.
+#[derive(Debug, Clone)]
+pub(super) struct Archive {
+    pub(super) pool: PgPool,
+}
+
+impl Archive {
+    async fn store(&self, submission: &Submission) -> Result<Uuid, sqlx::Error> {
+        let id = Uuid::new_v4();
+        let mut transaction = self.pool.begin().await?;
+        sqlx::query(
+            "INSERT INTO manifests (id, submitter, package, format, body)
+                VALUES ($1, $2, $3, $4, $5)",
+        )
+        .bind(id)
+        .bind(&submission.submitter)
+        .bind(&submission.package)
+        .bind(submission.format.media_type())
+        .bind(&submission.body)
+        .execute(&mut *transaction)
+        .await?;
+        let positions: Vec<i32> = (0..submission.orders.len() as i32).collect();
+        let items: Vec<&str> = submission.orders.iter().map(|o| o.item.as_str()).collect();
+        let quantities: Vec<Decimal> = submission.orders.iter().map(|o| o.quantity).collect();
+        sqlx::query(
+            "INSERT INTO manifest_orders (manifest_id, position, item, quantity)
+                SELECT $1, * FROM UNNEST($2::int[], $3::text[], $4::numeric[])",
+        )
+        .bind(id)
+        .bind(positions)
+        .bind(items)
+        .bind(quantities)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(id)
+    }
+
+    /// Failing to archive a manifest must not fail its submission, or hold it
+    /// up for long, so the manifest is archived in the background and errors
+    /// are logged rather than returned. The ID is returned if it is archived
+    /// within [`RECORD_TIMEOUT`].
+    pub(super) async fn record(&self, submission: Submission) -> Option<Uuid> {
+        let archive = self.clone();
+        let stored = tokio::spawn(async move {
+            archive
+                .store(&submission)
+                .await
+                .inspect_err(|e| eprintln!("Failed to archive manifest: {:?}", e))
+                .ok()
+        });
+        tokio::time::timeout(RECORD_TIMEOUT, stored)
+            .await
+            .ok()?
+            .ok()
+            .flatten()
+    }
+
+    async fn list(&self, params: &ListParams) -> Result<Vec<SubmissionSummary>, sqlx::Error> {
+        sqlx::query_as::<_, SubmissionSummary>(
+            "
+                SELECT m.id, m.submitter, m.package, m.format, m.created_at,
+                    COUNT(o.position) AS orders,
+                    COALESCE(SUM(o.quantity), 0)::text AS total
+                FROM manifests m
+                LEFT JOIN manifest_orders o ON o.manifest_id = m.id
+                WHERE ($1::text IS NULL OR m.submitter = $1)
+                GROUP BY m.id
+                ORDER BY m.created_at DESC, m.id
+                LIMIT $2 OFFSET $3
+                ",
+        )
+        .bind(&params.submitter)
+        .bind(params.limit.unwrap_or(DEFAULT_LIMIT).clamp(0, MAX_LIMIT))
+        .bind(params.offset.unwrap_or(0).max(0))
+        .fetch_all(&self.pool)
+        .await
+    }
+
+    async fn fetch(&self, id: Uuid) -> Result<Option<SubmissionRecord>, sqlx::Error> {
+        let Some(mut record) = sqlx::query_as::<_, SubmissionRecord>(
+            "SELECT id, submitter, package, format, created_at, body FROM manifests WHERE id = $1",
+        )
+        .bind(id)
+        .fetch_optional(&self.pool)
+        .await?
+        else {
+            return Ok(None);
+        };
+        record.orders = sqlx::query_as(
+            "SELECT item, quantity FROM manifest_orders WHERE manifest_id = $1 ORDER BY position",
+        )
+        .bind(id)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(Some(record))
+    }
+
+    async fn orders(&self, params: &OrderParams) -> Result<OrderReport, sqlx::Error> {
+        let orders = sqlx::query_as::<_, MatchingOrder>(
+            "
+                SELECT o.manifest_id AS submission, m.submitter, m.created_at, o.item, o.quantity
+                FROM manifest_orders o
+                JOIN manifests m ON m.id = o.manifest_id
+                WHERE ($1::text IS NULL OR LOWER(o.item) = LOWER($1))
+                    AND ($2::timestamptz IS NULL OR m.created_at >= $2)
+                    AND ($3::timestamptz IS NULL OR m.created_at < $3)
+                ORDER BY m.created_at, o.manifest_id, o.position
+                LIMIT $4 OFFSET $5
+                ",
+        )
+        .bind(&params.item)
+        .bind(params.from)
+        .bind(params.to)
+        .bind(params.limit.unwrap_or(DEFAULT_LIMIT).clamp(0, MAX_LIMIT))
+        .bind(params.offset.unwrap_or(0).max(0))
+        .fetch_all(&self.pool)
+        .await?;
+        let totals = sqlx::query_as::<_, ItemTotal>(
+            "
+                SELECT (ARRAY_AGG(o.item ORDER BY m.created_at, o.manifest_id, o.position))[1]
+                        AS item,
+                    COUNT(*) AS orders, SUM(o.quantity)::text AS quantity
+                FROM manifest_orders o
+                JOIN manifests m ON m.id = o.manifest_id
+                WHERE ($1::text IS NULL OR LOWER(o.item) = LOWER($1))
+                    AND ($2::timestamptz IS NULL OR m.created_at >= $2)
+                    AND ($3::timestamptz IS NULL OR m.created_at < $3)
+                GROUP BY LOWER(o.item)
+                ORDER BY LOWER(o.item)
+                ",
+        )
+        .bind(&params.item)
+        .bind(params.from)
+        .bind(params.to)
+        .fetch_all(&self.pool)
+        .await?;
+        let total = sqlx::query_scalar(
+            "
+                SELECT COALESCE(SUM(o.quantity), 0)::text
+                FROM manifest_orders o
+                JOIN manifests m ON m.id = o.manifest_id
+                WHERE ($1::text IS NULL OR LOWER(o.item) = LOWER($1))
+                    AND ($2::timestamptz IS NULL OR m.created_at >= $2)
+                    AND ($3::timestamptz IS NULL OR m.created_at < $3)
+                ",
+        )
+        .bind(&params.item)
+        .bind(params.from)
+        .bind(params.to)
+        .fetch_one(&self.pool)
+        .await?;
+        Ok(OrderReport {
+            total,
+            orders,
+            totals,
+        })
+    }
+}
+
+/// `GET /5/submissions`: the newest submissions first, with their order
+/// counts and totals.
+pub(super) async fn list_submissions(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<ListParams>,
+) -> Result<Json<Vec<SubmissionSummary>>, AppError> {
+    Ok(Json(state.archive.list(&params).await?))
+}
+
+/// `GET /5/submissions/:id`: one submission, with the manifest as sent and
+/// the orders read from it.
+pub(super) async fn get_submission(
+    State(state): State<Arc<AppState>>,
+    Path(id): Path<String>,
+) -> Result<Json<SubmissionRecord>, AppError> {
+    let id: Uuid = id.parse().map_err(|_| AppError::InvalidId(id))?;
+    let record = state.archive.fetch(id).await?;
+    Ok(Json(record.ok_or(AppError::SubmissionNotFound)?))
+}
+
+/// `GET /5/orders`: archived orders by item and submission time, with the
+/// quantity per item and overall.
+pub(super) async fn query_orders(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<OrderParams>,
+) -> Result<Json<OrderReport>, AppError> {
+    Ok(Json(state.archive.orders(&params).await?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn order(item: &str, quantity: i64) -> Reported {
+        Reported {
+            member: None,
+            item: item.to_string(),
+            quantity: quantity.into(),
+        }
+    }
+
+    fn submission(submitter: &str, orders: &[Reported]) -> Submission {
+        Submission {
+            submitter: submitter.to_string(),
+            package: Some("not-a-gift-order".to_string()),
+            format: Format::Toml,
+            body: "[package]\nname = \"not-a-gift-order\"\n".to_string(),
+            orders: orders.to_vec(),
+        }
+    }
+
+    #[sqlx::test]
+    async fn submissions_are_kept_with_their_orders(pool: PgPool) {
+        let archive = Archive { pool };
+        let orders = [order("Toy car", 2), order("Lego brick", 230)];
+        let id = archive.record(submission("santa", &orders)).await.unwrap();
+
+        let record = archive.fetch(id).await.unwrap().unwrap();
+        assert_eq!(record.submitter, "santa");
+        assert_eq!(record.format, "application/toml");
+        let items: Vec<_> = record.orders.iter().map(|o| o.item.as_str()).collect();
+        assert_eq!(items, vec!["Toy car", "Lego brick"]);
+        assert!(archive.fetch(Uuid::new_v4()).await.unwrap().is_none());
+
+        let params = ListParams {
+            submitter: Some("santa".to_string()),
+            limit: None,
+            offset: None,
+        };
+        let summaries = archive.list(&params).await.unwrap();
+        assert_eq!(summaries.len(), 1);
+        assert_eq!(
+            (summaries[0].orders, summaries[0].total.as_str()),
+            (2, "232")
+        );
+    }
+
+    #[sqlx::test]
+    async fn orders_are_queried_by_item_and_time(pool: PgPool) {
+        let archive = Archive { pool };
+        let before = Utc::now();
+        let first = [order("Toy car", 2), order("Lego brick", 230)];
+        let second = [order("toy car", 3)];
+        archive.record(submission("santa", &first)).await.unwrap();
+        archive.record(submission("elf", &second)).await.unwrap();
+
+        let params = OrderParams {
+            item: Some("TOY CAR".to_string()),
+            from: Some(before),
+            to: None,
+            limit: None,
+            offset: None,
+        };
+        let report = archive.orders(&params).await.unwrap();
+        assert_eq!(report.orders.len(), 2);
+        assert_eq!(report.totals.len(), 1);
+        assert_eq!(
+            (
+                report.totals[0].item.as_str(),
+                report.totals[0].quantity.as_str()
+            ),
+            ("Toy car", "5")
+        );
+        assert_eq!(report.total, "5");
+
+        let params = OrderParams {
+            limit: Some(1),
+            offset: Some(1),
+            ..params
+        };
+        let report = archive.orders(&params).await.unwrap();
+        assert_eq!(report.orders.len(), 1);
+        assert_eq!(report.orders[0].submitter, "elf");
+        assert_eq!(report.total, "5");
+
+        let params = OrderParams {
+            item: None,
+            from: None,
+            to: Some(before),
+            limit: None,
+            offset: None,
+        };
+        assert!(archive.orders(&params).await.unwrap().orders.is_empty());
+    }
+
+    #[sqlx::test]
+    async fn totals_beyond_a_decimal_are_reported(pool: PgPool) {
+        let archive = Archive { pool };
+        let stars = [
+            Reported {
+                quantity: Decimal::MAX,
+                ..order("Star", 0)
+            },
+            Reported {
+                quantity: Decimal::new(15, 1),
+                ..order("Star", 0)
+            },
+        ];
+        archive.record(submission("santa", &stars)).await.unwrap();
+
+        let params = ListParams {
+            submitter: None,
+            limit: None,
+            offset: None,
+        };
+        let summaries = archive.list(&params).await.unwrap();
+        assert_eq!(
+            serde_json::to_value(&summaries[0]).unwrap()["total"].to_string(),
+            "79228162514264337593543950336.5"
+        );
+        let params = OrderParams {
+            item: None,
+            from: None,
+            to: None,
+            limit: None,
+            offset: None,
+        };
+        let report = serde_json::to_value(archive.orders(&params).await.unwrap()).unwrap();
+        assert_eq!(report["total"], report["totals"][0]["quantity"]);
+        assert_eq!(
+            report["total"].to_string(),
+            "79228162514264337593543950336.5"
+        );
+    }
+}
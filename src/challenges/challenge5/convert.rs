@@ -0,0 +1,215 @@
+use axum::{
+    http::{
+        header::{ACCEPT, CONTENT_TYPE},
+        HeaderMap,
+    },
+    response::{IntoResponse, Response, Result},
+};
+use serde::Serialize;
+use serde_yaml::Value;
+
+use super::{AppError, Format};
+
+/// Key TOML datetimes are wrapped in when read into another data model.
+const TOML_DATETIME: &str = "$__toml_private_datetime";
+
+/// Key JSON numbers are wrapped in when read into or written to another data
+/// model, as `serde_json` keeps the literal text of every number.
+const JSON_NUMBER: &str = "$serde_json::private::Number";
+
+/// Read a whole document, keeping key order. [`serde_yaml::Value`] is used
+/// as the common model since its mappings keep insertion order; TOML
+/// datetimes become strings, as YAML and JSON have no datetime type.
+fn read(format: Format, body: &str) -> Result<Value, AppError> {
+    let invalid = |e: &dyn std::fmt::Display| AppError::InvalidDocument(e.to_string());
+    match format {
+        Format::Toml => toml::from_str(body).map(unwrap).map_err(|e| invalid(&e)),
+        Format::Yaml => serde_yaml::from_str(body).map_err(|e| invalid(&e)),
+        Format::Json => serde_json::from_str(body)
+            .map(unwrap)
+            .map_err(|e| invalid(&e)),
+    }
+}
+
+/// Replace TOML datetimes with strings and JSON numbers with YAML numbers.
+fn unwrap(value: Value) -> Value {
+    match value {
+        Value::Mapping(mapping) => {
+            if mapping.len() == 1 {
+                if let Some(Value::String(datetime)) = mapping.get(TOML_DATETIME) {
+                    return Value::String(datetime.clone());
+                }
+                if let Some(Value::String(number)) = mapping.get(JSON_NUMBER) {
+                    // Integers too large for YAML are read as floats, as YAML reads them.
+                    let number = number
+                        .parse()
+                        .or_else(|_| number.parse::<f64>().map(Into::into));
+                    if let Ok(number) = number {
+                        return Value::Number(number);
+                    }
+                }
+            }
+            Value::Mapping(
+                mapping
+                    .into_iter()
+                    .map(|(key, value)| (key, unwrap(value)))
+                    .collect(),
+            )
+        }
+        Value::Sequence(sequence) => Value::Sequence(sequence.into_iter().map(unwrap).collect()),
+        value => value,
+    }
+}
+
+/// Write a document. TOML puts a table's plain values before its subtables,
+/// but otherwise order is kept. JSON numbers are written as TOML and YAML
+/// numbers, which hold as many digits as an `f64`.
+pub(super) fn write<T: Serialize>(format: Format, document: &T) -> Result<String, AppError> {
+    let unconvertible = |e: &dyn std::fmt::Display| AppError::Unconvertible(e.to_string());
+    let native = || serde_yaml::to_value(document).map(unwrap);
+    match format {
+        Format::Toml => native()
+            .map_err(|e| unconvertible(&e))
+            .and_then(|document| toml::to_string(&document).map_err(|e| unconvertible(&e))),
+        Format::Yaml => native()
+            .and_then(|document| serde_yaml::to_string(&document))
+            .map_err(|e| unconvertible(&e)),
+        Format::Json => serde_json::to_string_pretty(document)
+            .map(|json| json + "\n")
+            .map_err(|e| unconvertible(&e)),
+    }
+}
+
+/// The format asked for by `Accept`, JSON when there is no `Accept` header.
+fn target(headers: &HeaderMap) -> Result<Format, AppError> {
+    let Some(accept) = headers.get(ACCEPT) else {
+        return Ok(Format::Json);
+    };
+    accept
+        .to_str()
+        .ok()
+        .and_then(Format::from_accept)
+        .ok_or(AppError::NotAcceptable)
+}
+
+/// `POST /5/convert`: the manifest in the body, in the format of its
+/// Content-Type, rewritten in the format asked for by `Accept`.
+pub(super) async fn convert(headers: HeaderMap, body: String) -> Result<Response, AppError> {
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .ok_or(AppError::MissingContentType)?
+        .to_str()
+        .map_err(|_| AppError::UnsupportedMediaType)?;
+    let from = Format::from_content_type(content_type).ok_or(AppError::UnsupportedMediaType)?;
+    let to = target(&headers)?;
+
+    let converted = write(to, &read(from, &body)?)?;
+    Ok(([(CONTENT_TYPE, to.media_type())], converted).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MANIFEST: &str = r#"
+[package]
+name = "not-a-gift-order"
+authors = ["Not Santa"]
+keywords = ["Christmas 2024"]
+released = 2024-12-05T00:00:00Z
+
+[[package.metadata.orders]]
+item = "Toy car"
+quantity = 2
+
+[[package.metadata.orders]]
+item = "Lego brick"
+quantity = 230
+
+[dependencies]
+serde = { version = "1", features = ["derive"] }
+"#;
+
+    fn convert(from: Format, to: Format, body: &str) -> String {
+        write(to, &read(from, body).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn toml_to_yaml_to_toml_keeps_the_meaning() {
+        let yaml = convert(Format::Toml, Format::Yaml, MANIFEST);
+        let toml = convert(Format::Yaml, Format::Toml, &yaml);
+
+        let mut original: toml::Table = toml::from_str(MANIFEST).unwrap();
+        original["package"]["released"] = "2024-12-05T00:00:00Z".into();
+        let round_tripped: toml::Table = toml::from_str(&toml).unwrap();
+        assert_eq!(round_tripped, original);
+    }
+
+    #[test]
+    fn toml_to_json_to_toml_keeps_the_meaning() {
+        let json = convert(Format::Toml, Format::Json, MANIFEST);
+        let toml = convert(Format::Json, Format::Toml, &json);
+        let yaml_via_json = convert(Format::Toml, Format::Yaml, &toml);
+        assert_eq!(yaml_via_json, convert(Format::Toml, Format::Yaml, MANIFEST));
+    }
+
+    #[test]
+    fn key_order_is_kept() {
+        let json = r#"{"package": {"version": "0.1.0", "name": "z", "edition": "2021"}}"#;
+        assert_eq!(
+            convert(Format::Json, Format::Yaml, json),
+            "package:\n  version: 0.1.0\n  name: z\n  edition: '2021'\n"
+        );
+        assert_eq!(
+            convert(Format::Json, Format::Toml, json),
+            "[package]\nversion = \"0.1.0\"\nname = \"z\"\nedition = \"2021\"\n"
+        );
+    }
+
+    #[test]
+    fn json_is_the_default_target() {
+        let accept = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert(ACCEPT, value.parse().unwrap());
+            headers
+        };
+        assert_eq!(target(&HeaderMap::new()).unwrap(), Format::Json);
+        assert_eq!(target(&accept("application/yaml")).unwrap(), Format::Yaml);
+        assert!(matches!(
+            target(&accept("text/html")),
+            Err(AppError::NotAcceptable)
+        ));
+    }
+
+    #[test]
+    fn json_numbers_are_written_as_numbers() {
+        let json =
+            r#"{"orders": [{"item": "Ribbon", "quantity": 1.5}], "total": 18446744073709551616}"#;
+        assert_eq!(
+            convert(Format::Json, Format::Toml, json),
+            "total = 18446744073709552000.0\n\n[[orders]]\nitem = \"Ribbon\"\nquantity = 1.5\n"
+        );
+        assert_eq!(
+            convert(Format::Json, Format::Yaml, json),
+            "orders:\n- item: Ribbon\n  quantity: 1.5\ntotal: 1.8446744073709552e19\n"
+        );
+        let written = serde_json::json!({"quantity": 2, "total": 3.5});
+        assert_eq!(
+            write(Format::Toml, &written).unwrap(),
+            "quantity = 2\ntotal = 3.5\n"
+        );
+        assert_eq!(
+            write(Format::Yaml, &written).unwrap(),
+            "quantity: 2\ntotal: 3.5\n"
+        );
+    }
+
+    #[test]
+    fn values_toml_cannot_hold_are_errors() {
+        let document = read(Format::Yaml, "package:\n  name: ~\n").unwrap();
+        assert!(matches!(
+            write(Format::Toml, &document),
+            Err(AppError::Unconvertible(_))
+        ));
+    }
+}
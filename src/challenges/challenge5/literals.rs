@@ -0,0 +1,142 @@
+use serde::de::{self, DeserializeSeed, Deserializer, IgnoredAny, MapAccess, SeqAccess, Visitor};
+use serde_json::{Number, Value};
+use std::fmt;
+
+/// The number a literal stands for, if JSON writes it the same way once
+/// digit separators and a leading `+` are dropped. `inf`, `nan` and the
+/// like are left to the parser.
+fn number(literal: &str) -> Option<Number> {
+    let literal = literal.replace('_', "");
+    serde_json::from_str(literal.strip_prefix('+').unwrap_or(&literal)).ok()
+}
+
+/// Replace every float of a parsed TOML `document` with its literal in
+/// `body`. TOML floats are read as `f64`s, which hold only about 16
+/// significant digits, so `9007199254740993.5` would read as
+/// `9007199254740994`; `serde_json`'s `arbitrary_precision` keeps the
+/// literal as written.
+pub(super) fn toml(body: &str, document: &mut Value) {
+    let Ok(written) = body.parse::<toml_edit::DocumentMut>() else {
+        return;
+    };
+    let written = toml_edit::Value::InlineTable(written.as_table().clone().into_inline_table());
+    toml_floats(document, &written);
+}
+
+fn toml_floats(value: &mut Value, written: &toml_edit::Value) {
+    match (value, written) {
+        (Value::Number(value), toml_edit::Value::Float(float)) => {
+            let literal = float.as_repr().and_then(|repr| repr.as_raw().as_str());
+            if let Some(literal) = literal.and_then(number) {
+                *value = literal;
+            }
+        }
+        (Value::Array(values), toml_edit::Value::Array(written)) => {
+            for (value, written) in values.iter_mut().zip(written.iter()) {
+                toml_floats(value, written);
+            }
+        }
+        (Value::Object(values), toml_edit::Value::InlineTable(written)) => {
+            for (key, value) in values.iter_mut() {
+                if let Some(written) = written.get(key) {
+                    toml_floats(value, written);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Read `body` again with every number of the parsed YAML `document` as
+/// written, since YAML floats are read as `f64`s like TOML's. `serde_yaml`
+/// gives the text of any scalar asked for as a string, so the document guides
+/// a second pass that asks for one wherever it has a number. Anything the
+/// second pass can't read keeps the first reading.
+pub(super) fn yaml(body: &str, document: Value) -> Value {
+    Written(&document)
+        .deserialize(serde_yaml::Deserializer::from_str(body))
+        .unwrap_or(document)
+}
+
+/// The value read at this point of the first pass.
+struct Written<'a>(&'a Value);
+
+impl<'de> DeserializeSeed<'de> for Written<'_> {
+    type Value = Value;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Value, D::Error> {
+        match self.0 {
+            Value::Number(_) => deserializer.deserialize_str(self),
+            Value::Array(_) => deserializer.deserialize_seq(self),
+            Value::Object(_) => deserializer.deserialize_map(self),
+            value => {
+                deserializer.deserialize_ignored_any(IgnoredAny)?;
+                Ok(value.clone())
+            }
+        }
+    }
+}
+
+impl<'de> Visitor<'de> for Written<'_> {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+
+    fn visit_str<E: de::Error>(self, literal: &str) -> Result<Value, E> {
+        Ok(number(literal).map_or_else(|| self.0.clone(), Value::Number))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Value, A::Error> {
+        let mut values = vec![];
+        for item in self.0.as_array().into_iter().flatten() {
+            values.push(seq.next_element_seed(Written(item))?.unwrap_or(Value::Null));
+        }
+        while seq.next_element::<IgnoredAny>()?.is_some() {}
+        Ok(Value::Array(values))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<Value, A::Error> {
+        let mut values = serde_json::Map::new();
+        while let Some(key) = map.next_key::<String>()? {
+            let value = match self.0.get(&key) {
+                Some(written) => map.next_value_seed(Written(written))?,
+                None => {
+                    map.next_value::<IgnoredAny>()?;
+                    continue;
+                }
+            };
+            values.insert(key, value);
+        }
+        Ok(Value::Object(values))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn floats_keep_every_digit() {
+        let body = "quantity = 9_007_199_254_740_993.5\nexponent = +1.5e3\nnot = nan\n";
+        let mut document =
+            serde_json::to_value(toml::from_str::<toml::Value>(body).unwrap()).unwrap();
+        toml(body, &mut document);
+        assert_eq!(document["quantity"].to_string(), "9007199254740993.5");
+        assert_eq!(document["exponent"].as_f64(), Some(1500.0));
+        assert!(document["not"].is_null());
+
+        let body =
+            "orders:\n  - {quantity: 9007199254740993.5, note: '1.5'}\n  - 18446744073709551616\n";
+        let document = yaml(body, serde_yaml::from_str(body).unwrap());
+        assert_eq!(
+            document,
+            json!({"orders": [
+                {"quantity": number("9007199254740993.5").unwrap(), "note": "1.5"},
+                number("18446744073709551616").unwrap(),
+            ]})
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use rust_decimal::Decimal;
+use serde::Serialize;
+use serde_json::{Number, Value};
+
+/// Why an order is skipped, and the field at fault if it is one field.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Rejection {
+    pub(super) field: Option<&'static str>,
+    pub(super) reason: &'static str,
+    /// The quantity cannot be represented exactly, which rejects the whole
+    /// manifest rather than just the order.
+    pub(super) out_of_range: bool,
+}
+
+impl Rejection {
+    fn new(field: &'static str, reason: &'static str) -> Self {
+        Rejection {
+            field: Some(field),
+            reason,
+            out_of_range: false,
+        }
+    }
+
+    fn out_of_range() -> Self {
+        Rejection {
+            out_of_range: true,
+            ..Rejection::new("quantity", "`quantity` is out of range")
+        }
+    }
+
+    /// Dotted path of the offending value in the `index`th order.
+    pub(super) fn path(&self, index: usize) -> String {
+        let order = format!("package.metadata.orders[{}]", index);
+        match self.field {
+            Some(field) => format!("{}.{}", order, field),
+            None => order,
+        }
+    }
+}
+
+/// An order with an item and a quantity of the right type.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct ValidOrder {
+    pub(super) item: String,
+    pub(super) quantity: Decimal,
+}
+
+/// A quantity exactly as written, read from its literal text. `None` when
+/// it does not fit a [`Decimal`].
+pub(super) fn exact_quantity(quantity: &Number) -> Option<Decimal> {
+    let literal = quantity.to_string();
+    if literal.contains(['e', 'E']) {
+        Decimal::from_scientific(&literal).ok()
+    } else {
+        Decimal::from_str_exact(&literal).ok()
+    }
+}
+
+/// Read one entry of `package.metadata.orders`. Fractional quantities are
+/// skipped unless `fractional` is set.
+pub(super) fn read_order(order: &Value, fractional: bool) -> Result<ValidOrder, Rejection> {
+    if !order.is_object() {
+        return Err(Rejection {
+            field: None,
+            reason: "not a table",
+            out_of_range: false,
+        });
+    }
+    let item = order
+        .get("item")
+        .and_then(Value::as_str)
+        .ok_or(Rejection::new("item", "`item` must be a string"))?;
+    let quantity = match order.get("quantity") {
+        None => return Err(Rejection::new("quantity", "no `quantity`")),
+        Some(Value::Number(quantity)) => {
+            exact_quantity(quantity).ok_or_else(Rejection::out_of_range)?
+        }
+        Some(_) => return Err(Rejection::new("quantity", "`quantity` must be a number")),
+    };
+    if !fractional && !quantity.fract().is_zero() {
+        return Err(Rejection::new(
+            "quantity",
+            "`quantity` must be a whole number",
+        ));
+    }
+    Ok(ValidOrder {
+        item: item.to_string(),
+        quantity,
+    })
+}
+
+/// The quantity of a valid `order` placed `count` times, as aggregates count
+/// it. `count` is 1 unless given.
+pub(super) fn read_total(order: &Value, quantity: Decimal) -> Result<Decimal, Rejection> {
+    let count = match order.get("count") {
+        None => 1,
+        Some(count) => count
+            .as_u64()
+            .and_then(|count| u32::try_from(count).ok())
+            .ok_or(Rejection::new(
+                "count",
+                "`count` must be a non-negative integer",
+            ))?,
+    };
+    quantity
+        .checked_mul(count.into())
+        .ok_or_else(Rejection::out_of_range)
+}
+
+/// An order as reported by `/5/manifest` and `/5/workspace`.
+#[derive(Debug, Clone, PartialEq)]
+pub(super) struct Reported {
+    /// The workspace member the order came from.
+    pub(super) member: Option<String>,
+    pub(super) item: String,
+    pub(super) quantity: Decimal,
+}
+
+#[derive(Debug, Serialize)]
+struct Total {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    member: Option<String>,
+    item: String,
+    quantity: Value,
+}
+
+/// The orders reported by `/5/manifest`, as a document for clients that ask
+/// for one with `Accept`.
+#[derive(Debug, Serialize)]
+pub(super) struct Listing {
+    package: Option<String>,
+    total: Value,
+    orders: Vec<Total>,
+}
+
+impl Listing {
+    /// `None` when the total does not fit a [`Decimal`].
+    pub(super) fn new(package: Option<String>, orders: Vec<Reported>) -> Option<Self> {
+        let total = orders.iter().try_fold(Decimal::ZERO, |total, order| {
+            total.checked_add(order.quantity)
+        })?;
+        Some(Listing {
+            package,
+            total: number(total),
+            orders: orders
+                .into_iter()
+                .map(|order| Total {
+                    member: order.member,
+                    item: order.item,
+                    quantity: number(order.quantity),
+                })
+                .collect(),
+        })
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Skipped {
+    index: usize,
+    path: String,
+    reason: &'static str,
+}
+
+/// The orders summed per item, as returned by `/5/manifest?aggregate=true`.
+#[derive(Debug, Serialize)]
+pub(super) struct Aggregate {
+    orders: Vec<Total>,
+    skipped: Vec<Skipped>,
+}
+
+/// Sum `quantity * count` per item, in order of first appearance, noting
+/// every order skipped and why.
+pub(super) fn aggregate(orders: &[Value], fractional: bool) -> Aggregate {
+    let mut totals: Vec<(String, Decimal)> = vec![];
+    let mut skipped = vec![];
+    for (index, order) in orders.iter().enumerate() {
+        let read = read_order(order, fractional)
+            .and_then(|valid| Ok((read_total(order, valid.quantity)?, valid.item)));
+        let (quantity, item) = match read {
+            Ok(read) => read,
+            Err(rejection) => {
+                skipped.push(Skipped {
+                    index,
+                    path: rejection.path(index),
+                    reason: rejection.reason,
+                });
+                continue;
+            }
+        };
+        match totals
+            .iter_mut()
+            .find(|(total_item, _)| *total_item == item)
+        {
+            Some((_, total)) => match total.checked_add(quantity) {
+                Some(sum) => *total = sum,
+                None => skipped.push(Skipped {
+                    index,
+                    path: format!("package.metadata.orders[{}]", index),
+                    reason: "total quantity of the item is out of range",
+                }),
+            },
+            None => totals.push((item, quantity)),
+        }
+    }
+    Aggregate {
+        orders: totals
+            .into_iter()
+            .map(|(item, quantity)| Total {
+                member: None,
+                item,
+                quantity: number(quantity),
+            })
+            .collect(),
+        skipped,
+    }
+}
+
+/// A quantity as a JSON number, whole quantities as integers.
+pub(super) fn number(quantity: Decimal) -> Value {
+    Value::Number(
+        quantity
+            .normalize()
+            .to_string()
+            .parse()
+            .expect("decimals are JSON numbers"),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn quantities_are_summed_per_item_with_counts() {
+        let orders = json!([
+            {"item": "Toy car", "quantity": 2},
+            {"item": "Lego brick", "quantity": 230},
+            {"item": "Toy car", "quantity": 3, "count": 2},
+        ]);
+        let aggregate = aggregate(orders.as_array().unwrap(), false);
+        assert_eq!(
+            json!(aggregate),
+            json!({
+                "orders": [
+                    {"item": "Toy car", "quantity": 8},
+                    {"item": "Lego brick", "quantity": 230},
+                ],
+                "skipped": [],
+            })
+        );
+    }
+
+    #[test]
+    fn skipped_orders_say_why() {
+        let orders = json!([
+            {"item": "Toy car", "quantity": "two"},
+            {"item": "Toy car", "quantity": 1.5},
+            {"item": "Toy car", "quantity": 1, "count": -1},
+            {"quantity": 1},
+            "Lego brick",
+        ]);
+        let aggregate = aggregate(orders.as_array().unwrap(), false);
+        assert!(aggregate.orders.is_empty());
+        let reasons: Vec<_> = aggregate
+            .skipped
+            .iter()
+            .map(|skipped| (skipped.path.as_str(), skipped.reason))
+            .collect();
+        assert_eq!(
+            reasons,
+            vec![
+                (
+                    "package.metadata.orders[0].quantity",
+                    "`quantity` must be a number"
+                ),
+                (
+                    "package.metadata.orders[1].quantity",
+                    "`quantity` must be a whole number"
+                ),
+                (
+                    "package.metadata.orders[2].count",
+                    "`count` must be a non-negative integer"
+                ),
+                ("package.metadata.orders[3].item", "`item` must be a string"),
+                ("package.metadata.orders[4]", "not a table"),
+            ]
+        );
+    }
+
+    #[test]
+    fn quantities_near_the_limits_of_f32_are_exact() {
+        // f32 has a 24-bit mantissa: it would read 16777217 as 16777216 and
+        // 16777216.5 as a whole number.
+        let orders = json!([
+            {"item": "Snowflake", "quantity": 16777217},
+            {"item": "Icicle", "quantity": 16777216.5},
+            {"item": "Tinsel", "quantity": 0.1, "count": 3},
+        ]);
+        let whole = aggregate(orders.as_array().unwrap(), false);
+        assert_eq!(
+            json!(whole.orders),
+            json!([{"item": "Snowflake", "quantity": 16777217}])
+        );
+        assert_eq!(whole.skipped[0].path, "package.metadata.orders[1].quantity");
+
+        let fractional = aggregate(orders.as_array().unwrap(), true);
+        assert_eq!(json!(fractional.orders[1].quantity), json!(16777216.5));
+        assert_eq!(json!(fractional.orders[2].quantity), json!(0.3));
+    }
+
+    #[test]
+    fn quantities_beyond_f64_are_read_as_written() {
+        // Above 2^53 an f64 can't even hold every integer.
+        let order: Value =
+            serde_json::from_str(r#"{"item": "Star", "quantity": 9007199254740993.5}"#).unwrap();
+        let order = read_order(&order, true).unwrap();
+        assert_eq!(order.quantity.to_string(), "9007199254740993.5");
+        let huge: Number = serde_json::from_str("123456789012345678901234567.89").unwrap();
+        assert_eq!(
+            exact_quantity(&huge).unwrap().to_string(),
+            "123456789012345678901234567.89"
+        );
+        let scientific: Number = serde_json::from_str("1.5E3").unwrap();
+        assert_eq!(exact_quantity(&scientific), Some(Decimal::from(1500)));
+    }
+
+    #[test]
+    fn quantities_beyond_a_decimal_are_out_of_range() {
+        for quantity in [
+            json!(f32::MAX),
+            json!(-f32::MAX),
+            json!(1e-45),
+            json!(u64::MAX),
+        ] {
+            let order = json!({"item": "Star", "quantity": quantity});
+            let result = read_order(&order, true);
+            match quantity.as_u64() {
+                Some(max) => assert_eq!(result.unwrap().quantity, Decimal::from(max)),
+                None => assert!(result.unwrap_err().out_of_range, "{}", quantity),
+            }
+        }
+        let order = json!({"item": "Star", "quantity": 1e28, "count": 10});
+        let quantity = read_order(&order, true).unwrap().quantity;
+        assert!(read_total(&order, quantity).unwrap_err().out_of_range);
+    }
+
+    #[test]
+    fn listings_have_a_total() {
+        let order = |member: Option<&str>, item: &str, quantity| Reported {
+            member: member.map(str::to_string),
+            item: item.to_string(),
+            quantity,
+        };
+        let orders = vec![
+            order(None, "Toy car", Decimal::from(2)),
+            order(Some("elves"), "Ribbon", Decimal::new(15, 1)),
+        ];
+        let listing = Listing::new(Some("not-a-gift-order".to_string()), orders).unwrap();
+        assert_eq!(
+            json!(listing),
+            json!({
+                "package": "not-a-gift-order",
+                "total": 3.5,
+                "orders": [
+                    {"item": "Toy car", "quantity": 2},
+                    {"member": "elves", "item": "Ribbon", "quantity": 1.5},
+                ],
+            })
+        );
+        let huge = vec![order(None, "Star", Decimal::MAX); 2];
+        assert!(Listing::new(None, huge).is_none());
+    }
+
+    #[test]
+    fn fractional_quantities_are_opt_in() {
+        let orders = json!([
+            {"item": "Ribbon", "quantity": 1.5},
+            {"item": "Ribbon", "quantity": 0.25, "count": 2},
+        ]);
+        let aggregate = aggregate(orders.as_array().unwrap(), true);
+        assert_eq!(
+            json!(aggregate.orders),
+            json!([{"item": "Ribbon", "quantity": 2}])
+        );
+    }
+}
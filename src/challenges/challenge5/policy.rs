@@ -0,0 +1,317 @@
+use serde::Deserialize;
+use serde_json::Value;
+use shuttle_runtime::SecretStore;
+use std::{cmp::Ordering, collections::HashMap};
+
+use super::{
+    rust_version::{self, RustVersion},
+    MAGIC_KEYWORD,
+};
+
+/// Secret holding the policy configuration itself, as TOML.
+const POLICY_SECRET: &str = "MANIFEST_POLICY";
+
+/// Secret naming a TOML file to read the policy configuration from.
+const POLICY_FILE_SECRET: &str = "MANIFEST_POLICY_FILE";
+
+/// A rule a manifest can break, each with its own error code.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) enum Rule {
+    MissingKeyword,
+    RustVersionOutOfRange,
+    MissingMetadataField,
+    BannedItem,
+}
+
+impl Rule {
+    pub(super) fn code(&self) -> &'static str {
+        match self {
+            Rule::MissingKeyword => "missing_keyword",
+            Rule::RustVersionOutOfRange => "rust_version_out_of_range",
+            Rule::MissingMetadataField => "missing_metadata_field",
+            Rule::BannedItem => "banned_item",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(super) struct Violation {
+    pub(super) rule: Rule,
+    /// Dotted path of the offending or missing value.
+    pub(super) path: String,
+    pub(super) message: String,
+}
+
+/// Inclusive bounds on `rust-version`, either of which may be left open. The
+/// minimum is the oldest MSRV the team still supports.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(super) struct VersionRange {
+    min: Option<RustVersion>,
+    max: Option<RustVersion>,
+}
+
+impl VersionRange {
+    fn compare(&self, version: &RustVersion) -> Ordering {
+        match (&self.min, &self.max) {
+            (Some(min), _) if version < min => Ordering::Less,
+            (_, Some(max)) if version > max => Ordering::Greater,
+            _ => Ordering::Equal,
+        }
+    }
+}
+
+/// The rules a manifest must meet for its orders to be read.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+pub(super) struct Policy {
+    /// Keywords the package must list, all of them.
+    #[serde(default = "Policy::default_keywords")]
+    keywords: Vec<String>,
+    #[serde(default)]
+    rust_version: VersionRange,
+    /// Keys `package.metadata` must have.
+    #[serde(default)]
+    required_metadata: Vec<String>,
+    /// Items no order may be for, compared case-insensitively.
+    #[serde(default)]
+    banned_items: Vec<String>,
+}
+
+impl Default for Policy {
+    fn default() -> Self {
+        Policy {
+            keywords: Policy::default_keywords(),
+            rust_version: VersionRange::default(),
+            required_metadata: vec![],
+            banned_items: vec![],
+        }
+    }
+}
+
+impl Policy {
+    fn default_keywords() -> Vec<String> {
+        vec![MAGIC_KEYWORD.to_string()]
+    }
+
+    /// Every rule `package` breaks, in the order the rules are listed.
+    pub(super) fn violations(&self, package: &Value) -> Vec<Violation> {
+        let mut violations = vec![];
+        let mut violate = |rule, path: String, message| {
+            violations.push(Violation {
+                rule,
+                path,
+                message,
+            })
+        };
+
+        let keywords = package.get("keywords").and_then(Value::as_array);
+        for keyword in &self.keywords {
+            if !keywords.is_some_and(|keywords| keywords.iter().any(|k| k == keyword.as_str())) {
+                let message = match keyword.as_str() {
+                    MAGIC_KEYWORD => "Magic keyword not provided".to_string(),
+                    keyword => format!("Required keyword `{}` not provided", keyword),
+                };
+                violate(
+                    Rule::MissingKeyword,
+                    "package.keywords".to_string(),
+                    message,
+                );
+            }
+        }
+
+        // An invalid rust-version is reported by `rust_version::read` instead.
+        if let Ok(Some(version)) = rust_version::read(package) {
+            let message =
+                match self.rust_version.compare(&version) {
+                    Ordering::Less => self.rust_version.min.as_ref().map(|min| {
+                        format!("rust-version {} is below the minimum {}", version, min)
+                    }),
+                    Ordering::Greater => self.rust_version.max.as_ref().map(|max| {
+                        format!("rust-version {} is above the maximum {}", version, max)
+                    }),
+                    Ordering::Equal => None,
+                };
+            if let Some(message) = message {
+                let path = "package.rust-version".to_string();
+                violate(Rule::RustVersionOutOfRange, path, message);
+            }
+        }
+
+        let metadata = package.get("metadata");
+        for field in &self.required_metadata {
+            if metadata.and_then(|metadata| metadata.get(field)).is_none() {
+                violate(
+                    Rule::MissingMetadataField,
+                    format!("package.metadata.{}", field),
+                    format!("Required metadata field `{}` not provided", field),
+                );
+            }
+        }
+
+        let orders = package
+            .pointer("/metadata/orders")
+            .and_then(Value::as_array)
+            .map_or(&[][..], Vec::as_slice);
+        for (index, order) in orders.iter().enumerate() {
+            let Some(item) = order.get("item").and_then(Value::as_str) else {
+                continue;
+            };
+            if self
+                .banned_items
+                .iter()
+                .any(|banned| banned.eq_ignore_ascii_case(item))
+            {
+                violate(
+                    Rule::BannedItem,
+                    format!("package.metadata.orders[{}].item", index),
+                    format!("Item `{}` is banned", item),
+                );
+            }
+        }
+
+        violations
+    }
+}
+
+/// The default policy, plus named policies chosen with `?policy=`, so each
+/// team can have its own acceptance rules.
+///
+/// ```toml
+/// keywords = ["Christmas 2024"]
+///
+/// [policies.reindeer]
+/// keywords = ["Christmas 2024", "Reindeer"]
+/// rust-version = { min = "1.74", max = "1.83" }
+/// required-metadata = ["orders", "sleigh"]
+/// banned-items = ["Coal"]
+/// ```
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(try_from = "toml::Table")]
+pub(super) struct Policies {
+    default: Policy,
+    policies: HashMap<String, Policy>,
+}
+
+/// The default policy's rules sit beside `[policies]`. They are read apart
+/// from it, rather than flattened, so a misspelt rule is rejected like it is
+/// in a named policy.
+impl TryFrom<toml::Table> for Policies {
+    type Error = toml::de::Error;
+
+    fn try_from(mut config: toml::Table) -> Result<Self, Self::Error> {
+        let policies = match config.remove("policies") {
+            Some(policies) => policies.try_into()?,
+            None => HashMap::new(),
+        };
+        Ok(Policies {
+            default: toml::Value::Table(config).try_into()?,
+            policies,
+        })
+    }
+}
+
+impl Policies {
+    pub(super) fn from_secrets(secrets: &SecretStore) -> Self {
+        let config = match (secrets.get(POLICY_SECRET), secrets.get(POLICY_FILE_SECRET)) {
+            (Some(config), _) => config,
+            (None, Some(path)) => std::fs::read_to_string(&path)
+                .unwrap_or_else(|e| panic!("Cannot read {POLICY_FILE_SECRET} `{path}`: {e}")),
+            (None, None) => return Policies::default(),
+        };
+        toml::from_str(&config).unwrap_or_else(|e| panic!("Invalid manifest policy: {e}"))
+    }
+
+    /// The named policy, or the default one when no name is given.
+    pub(super) fn get(&self, name: Option<&str>) -> Option<&Policy> {
+        match name {
+            None => Some(&self.default),
+            Some(name) => self.policies.get(name),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    const CONFIG: &str = r#"
+        [policies.reindeer]
+        keywords = ["Christmas 2024", "Reindeer"]
+        rust-version = { min = "1.74", max = "1.83" }
+        required-metadata = ["orders", "sleigh"]
+        banned-items = ["Coal"]
+    "#;
+
+    fn codes(policy: &Policy, package: Value) -> Vec<&'static str> {
+        policy
+            .violations(&package)
+            .iter()
+            .map(|violation| violation.rule.code())
+            .collect()
+    }
+
+    #[test]
+    fn default_policy_only_needs_the_magic_keyword() {
+        let policies = Policies::default();
+        let policy = policies.get(None).unwrap();
+        assert!(codes(policy, json!({"keywords": ["Christmas 2024"]})).is_empty());
+        let violations = policy.violations(&json!({"keywords": []}));
+        assert_eq!(violations[0].message, "Magic keyword not provided");
+    }
+
+    #[test]
+    fn every_rule_has_its_own_code() {
+        let policies: Policies = toml::from_str(CONFIG).unwrap();
+        let reindeer = policies.get(Some("reindeer")).unwrap();
+        let package = json!({
+            "keywords": ["Christmas 2024"],
+            "rust-version": "1.70",
+            "metadata": {"orders": [{"item": "Toy car"}, {"item": "coal"}]},
+        });
+        assert_eq!(
+            codes(reindeer, package),
+            vec![
+                "missing_keyword",
+                "rust_version_out_of_range",
+                "missing_metadata_field",
+                "banned_item",
+            ]
+        );
+        assert!(policies.get(Some("elves")).is_none());
+    }
+
+    #[test]
+    fn rust_version_range_is_inclusive() {
+        let policies: Policies = toml::from_str(CONFIG).unwrap();
+        let reindeer = policies.get(Some("reindeer")).unwrap();
+        for (version, in_range) in [
+            ("1.73.9", false),
+            ("1.74", true),
+            ("1.83.0", true),
+            ("1.83.1", false),
+        ] {
+            let package = json!({
+                "keywords": ["Christmas 2024", "Reindeer"],
+                "rust-version": version,
+                "metadata": {"orders": [], "sleigh": "red"},
+            });
+            assert_eq!(codes(reindeer, package).is_empty(), in_range, "{}", version);
+        }
+    }
+
+    #[test]
+    fn invalid_configuration_is_rejected() {
+        assert!(toml::from_str::<Policies>("rust-version = { min = \"one\" }").is_err());
+        assert!(toml::from_str::<Policies>("rust-version = { min = \"^1.74\" }").is_err());
+        assert!(toml::from_str::<Policies>("[policies.elves]\nbanned = [\"Coal\"]").is_err());
+        assert!(toml::from_str::<Policies>("banned-item = [\"Coal\"]").is_err());
+        assert!(toml::from_str::<Policies>("policies = [\"elves\"]").is_err());
+
+        let policies: Policies = toml::from_str("banned-items = [\"Coal\"]").unwrap();
+        let default = policies.get(None).unwrap();
+        assert_eq!(default.banned_items, vec!["Coal".to_string()]);
+        assert!(policies.get(Some("elves")).is_none());
+    }
+}
@@ -0,0 +1,164 @@
+use semver::{Op, Version, VersionReq};
+use serde::Deserialize;
+use serde_json::Value;
+use std::{cmp::Ordering, fmt, str::FromStr};
+
+/// A `package.rust-version`, read by Cargo's rules for the field: a bare
+/// version of one to three numeric components, such as `1`, `1.80` or
+/// `1.80.1`, with no requirement operator, wildcard, pre-release or build
+/// metadata. Missing components compare as 0, so `1.80` equals `1.80.0`.
+#[derive(Debug, Clone)]
+pub(super) struct RustVersion {
+    version: Version,
+    written: String,
+}
+
+impl PartialEq for RustVersion {
+    fn eq(&self, other: &Self) -> bool {
+        self.version == other.version
+    }
+}
+
+impl Eq for RustVersion {}
+
+impl PartialOrd for RustVersion {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for RustVersion {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.version.cmp(&other.version)
+    }
+}
+
+impl FromStr for RustVersion {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let expected = || format!("`{}` is not a version like \"1.80\"", s);
+        let version = match Version::parse(s) {
+            Ok(version) if !version.pre.is_empty() => {
+                return Err(format!("`{}` has a pre-release, which is not allowed", s))
+            }
+            Ok(version) if !version.build.is_empty() => {
+                return Err(format!("`{}` has build metadata, which is not allowed", s))
+            }
+            Ok(version) => version,
+            // Like Cargo, parse one or two components as a requirement, which
+            // must then be nothing but the implied caret.
+            Err(_) => {
+                if s.trim() != s || s.starts_with(|c: char| !c.is_ascii_digit()) {
+                    return Err(expected());
+                }
+                let requirement = VersionReq::parse(s).map_err(|_| expected())?;
+                match &requirement.comparators[..] {
+                    [comparator] if comparator.op == Op::Caret && comparator.pre.is_empty() => {
+                        Version::new(
+                            comparator.major,
+                            comparator.minor.unwrap_or(0),
+                            comparator.patch.unwrap_or(0),
+                        )
+                    }
+                    _ => return Err(expected()),
+                }
+            }
+        };
+        Ok(RustVersion {
+            version,
+            written: s.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for RustVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.written)
+    }
+}
+
+impl<'de> Deserialize<'de> for RustVersion {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Check `package.rust-version` as it appears in any manifest format.
+/// `Ok(None)` when it is absent or inherited from the workspace.
+pub(super) fn read(package: &Value) -> Result<Option<RustVersion>, String> {
+    match package.get("rust-version") {
+        None => Ok(None),
+        Some(Value::String(version)) => version.parse().map(Some),
+        Some(Value::Object(inherited))
+            if inherited.get("workspace") == Some(&Value::Bool(true)) =>
+        {
+            Ok(None)
+        }
+        Some(other) => Err(format!("{} is not a version like \"1.80\"", other)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn cargo_accepts_bare_versions() {
+        for (written, version) in [
+            ("1", Version::new(1, 0, 0)),
+            ("1.80", Version::new(1, 80, 0)),
+            ("1.80.1", Version::new(1, 80, 1)),
+            ("0.0.0", Version::new(0, 0, 0)),
+        ] {
+            let parsed: RustVersion = written.parse().unwrap();
+            assert_eq!(parsed.version, version);
+            assert_eq!(parsed.to_string(), written);
+        }
+    }
+
+    #[test]
+    fn cargo_rejects_anything_else() {
+        for written in [
+            "",
+            "1e3",
+            "1.80.1.2",
+            "v1.80",
+            "^1.80",
+            "~1.80",
+            ">=1.80",
+            "1.*",
+            "1.80.x",
+            "1.80-beta",
+            "1.80.0-beta.1",
+            "1.80.0+build",
+            "01.80",
+            " 1.80",
+            "1.80 ",
+            "1.80,1.81",
+            "one",
+        ] {
+            assert!(written.parse::<RustVersion>().is_err(), "{:?}", written);
+        }
+    }
+
+    #[test]
+    fn versions_compare_numerically() {
+        let version = |s: &str| s.parse::<RustVersion>().unwrap();
+        assert!(version("1.9") < version("1.10"));
+        assert!(version("1.80") < version("1.80.1"));
+        assert_eq!(version("1.80"), version("1.80.0"));
+    }
+
+    #[test]
+    fn every_format_is_read_alike() {
+        assert!(read(&json!({"rust-version": "1.80.1"})).unwrap().is_some());
+        assert!(read(&json!({"rust-version": {"workspace": true}}))
+            .unwrap()
+            .is_none());
+        assert!(read(&json!({"rust-version": 1.8})).is_err());
+        assert!(read(&json!({})).unwrap().is_none());
+    }
+}
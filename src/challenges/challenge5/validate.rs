@@ -0,0 +1,379 @@
+use cargo_manifest::Manifest;
+use serde::Serialize;
+use serde_json::Value;
+
+use super::{
+    literals,
+    orders::{read_order, read_total},
+    policy::Policy,
+    rust_version, Format,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub(super) enum Severity {
+    /// The manifest would be rejected.
+    Error,
+    /// The manifest is accepted, but part of it is ignored.
+    Warning,
+}
+
+/// One problem found in a manifest. Parse and schema errors carry the
+/// 1-based line and column they were found at, everything else the dotted
+/// path of the offending value.
+#[derive(Debug, Serialize)]
+pub(super) struct Issue {
+    severity: Severity,
+    code: &'static str,
+    message: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    line: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    column: Option<usize>,
+}
+
+impl Issue {
+    fn at_path(severity: Severity, code: &'static str, path: &str, message: String) -> Self {
+        Issue {
+            severity,
+            code,
+            message,
+            path: Some(path.to_string()),
+            line: None,
+            column: None,
+        }
+    }
+
+    fn at_location(code: &'static str, message: String, location: Option<(usize, usize)>) -> Self {
+        Issue {
+            severity: Severity::Error,
+            code,
+            message,
+            path: None,
+            line: location.map(|(line, _)| line),
+            column: location.map(|(_, column)| column),
+        }
+    }
+}
+
+/// Every issue found in a manifest, as returned by `/5/manifest?validate=true`.
+#[derive(Debug, Serialize)]
+pub(super) struct Report {
+    pub(super) valid: bool,
+    issues: Vec<Issue>,
+}
+
+/// 1-based line and column of a byte offset.
+fn line_column(body: &str, offset: usize) -> (usize, usize) {
+    let before = body.get(..offset).unwrap_or(body);
+    let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+    (
+        before.matches('\n').count() + 1,
+        before[line_start..].chars().count() + 1,
+    )
+}
+
+fn toml_location(body: &str, e: &toml::de::Error) -> Option<(usize, usize)> {
+    e.span().map(|span| line_column(body, span.start))
+}
+
+fn yaml_location(e: &serde_yaml::Error) -> Option<(usize, usize)> {
+    e.location()
+        .map(|location| (location.line(), location.column()))
+}
+
+fn json_location(e: &serde_json::Error) -> Option<(usize, usize)> {
+    (e.line() > 0).then(|| (e.line(), e.column()))
+}
+
+/// Parse the document, reporting where it fails. Numbers are kept as
+/// written, whatever the format.
+pub(super) fn parse(format: Format, body: &str) -> Result<Value, Issue> {
+    let parse_error = |message, location| Issue::at_location("parse_error", message, location);
+    match format {
+        Format::Toml => toml::from_str::<toml::Value>(body)
+            .map_err(|e| parse_error(e.message().to_string(), toml_location(body, &e)))
+            .map(|document| {
+                let mut document = serde_json::to_value(document).expect("TOML is valid JSON");
+                literals::toml(body, &mut document);
+                document
+            }),
+        Format::Yaml => serde_yaml::from_str(body)
+            .map_err(|e| parse_error(e.to_string(), yaml_location(&e)))
+            .map(|document| literals::yaml(body, document)),
+        Format::Json => {
+            serde_json::from_str(body).map_err(|e| parse_error(e.to_string(), json_location(&e)))
+        }
+    }
+}
+
+/// Check the document against the `cargo_manifest` schema.
+fn check_schema(format: Format, body: &str) -> Option<Issue> {
+    let invalid = |message, location| Issue::at_location("invalid_manifest", message, location);
+    match format {
+        Format::Toml => toml::from_str::<Manifest>(body)
+            .err()
+            .map(|e| invalid(e.message().to_string(), toml_location(body, &e))),
+        Format::Yaml => serde_yaml::from_str::<Manifest>(body)
+            .err()
+            .map(|e| invalid(e.to_string(), yaml_location(&e))),
+        Format::Json => serde_json::from_str::<Manifest>(body)
+            .err()
+            .map(|e| invalid(e.to_string(), json_location(&e))),
+    }
+}
+
+/// Checked the same way for every format, by Cargo's rules for the field.
+fn check_rust_version(package: &Value, issues: &mut Vec<Issue>) {
+    if let Err(reason) = rust_version::read(package) {
+        issues.push(Issue::at_path(
+            Severity::Error,
+            "invalid_rust_version",
+            "package.rust-version",
+            format!("Invalid rust-version: {}", reason),
+        ));
+    }
+}
+
+/// Orders that would be skipped, and a warning if none is left to report.
+fn check_orders(package: &Value, issues: &mut Vec<Issue>) {
+    let orders: &[Value] = match package.pointer("/metadata/orders") {
+        None => &[],
+        Some(Value::Array(orders)) => orders,
+        Some(_) => {
+            issues.push(Issue::at_path(
+                Severity::Warning,
+                "invalid_orders",
+                "package.metadata.orders",
+                "Orders must be an array".to_string(),
+            ));
+            &[]
+        }
+    };
+
+    let mut reported = 0;
+    for (index, order) in orders.iter().enumerate() {
+        match read_order(order, false).and_then(|valid| read_total(order, valid.quantity)) {
+            Ok(_) => reported += 1,
+            Err(rejection) if rejection.out_of_range => issues.push(Issue::at_path(
+                Severity::Error,
+                "quantity_out_of_range",
+                &rejection.path(index),
+                format!("Order rejected: {}", rejection.reason),
+            )),
+            Err(rejection) => issues.push(Issue::at_path(
+                Severity::Warning,
+                "invalid_order",
+                &rejection.path(index),
+                format!("Order skipped: {}", rejection.reason),
+            )),
+        }
+    }
+
+    if reported == 0 {
+        issues.push(Issue::at_path(
+            Severity::Warning,
+            "no_orders",
+            "package.metadata.orders",
+            "No valid orders to report".to_string(),
+        ));
+    }
+}
+
+/// Collect every issue in a manifest, rather than stopping at the first.
+/// Only a document that fails to parse stops the checks early.
+pub(super) fn validate(format: Format, body: &str, policy: &Policy) -> Report {
+    let mut issues = vec![];
+    match parse(format, body) {
+        Err(issue) => issues.push(issue),
+        Ok(document) => {
+            issues.extend(check_schema(format, body));
+            match document.get("package") {
+                Some(package) if package.is_object() => {
+                    check_rust_version(package, &mut issues);
+                    issues.extend(policy.violations(package).into_iter().map(|violation| {
+                        Issue::at_path(
+                            Severity::Error,
+                            violation.rule.code(),
+                            &violation.path,
+                            violation.message,
+                        )
+                    }));
+                    check_orders(package, &mut issues);
+                }
+                _ => issues.push(Issue::at_path(
+                    Severity::Error,
+                    "missing_package",
+                    "package",
+                    "No [package] table".to_string(),
+                )),
+            }
+        }
+    }
+    Report {
+        valid: issues.iter().all(|issue| issue.severity != Severity::Error),
+        issues,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn codes(report: &Report) -> Vec<(&'static str, Severity)> {
+        report
+            .issues
+            .iter()
+            .map(|issue| (issue.code, issue.severity))
+            .collect()
+    }
+
+    #[test]
+    fn valid_manifest_has_no_issues() {
+        let body = r#"
+[package]
+name = "not-a-gift-order"
+keywords = ["Christmas 2024"]
+rust-version = "1.83"
+
+[[package.metadata.orders]]
+item = "Toy car"
+quantity = 2
+"#;
+        let report = validate(Format::Toml, body, &Policy::default());
+        assert!(report.valid);
+        assert!(report.issues.is_empty(), "{:?}", report.issues);
+    }
+
+    #[test]
+    fn quantities_are_read_as_written_in_every_format() {
+        for (format, body) in [
+            (
+                Format::Toml,
+                "[[package.metadata.orders]]\nitem = \"Star\"\nquantity = 9007199254740993.5\n",
+            ),
+            (
+                Format::Yaml,
+                "package:\n  metadata:\n    orders:\n      - item: Star\n        quantity: 9007199254740993.5\n",
+            ),
+            (
+                Format::Json,
+                r#"{"package": {"metadata": {"orders": [{"item": "Star", "quantity": 9007199254740993.5}]}}}"#,
+            ),
+        ] {
+            let document = parse(format, body).unwrap();
+            let order = read_order(&document["package"]["metadata"]["orders"][0], true).unwrap();
+            assert_eq!(order.quantity.to_string(), "9007199254740993.5", "{:?}", format);
+        }
+    }
+
+    #[test]
+    fn every_issue_is_collected() {
+        let body = r#"
+[package]
+name = "not-a-gift-order"
+rust-version = "1.83-beta"
+
+[[package.metadata.orders]]
+item = "Toy car"
+quantity = "two"
+
+[[package.metadata.orders]]
+item = 7
+quantity = 1
+"#;
+        let report = validate(Format::Toml, body, &Policy::default());
+        assert!(!report.valid);
+        assert_eq!(
+            codes(&report),
+            vec![
+                ("invalid_rust_version", Severity::Error),
+                ("missing_keyword", Severity::Error),
+                ("invalid_order", Severity::Warning),
+                ("invalid_order", Severity::Warning),
+                ("no_orders", Severity::Warning),
+            ]
+        );
+        assert_eq!(
+            report.issues[2].path.as_deref(),
+            Some("package.metadata.orders[0].quantity")
+        );
+    }
+
+    #[test]
+    fn rust_version_is_checked_alike_in_every_format() {
+        for (version, valid) in [("1.80.1", true), ("1e3", false), ("1.80.0-beta", false)] {
+            let toml = format!(
+                "[package]\nname = \"x\"\nkeywords = [\"Christmas 2024\"]\nrust-version = \"{}\"\n",
+                version
+            );
+            let yaml = format!(
+                "package:\n  name: x\n  keywords: [Christmas 2024]\n  rust-version: '{}'\n",
+                version
+            );
+            let json = format!(
+                r#"{{"package": {{"name": "x", "keywords": ["Christmas 2024"], "rust-version": "{}"}}}}"#,
+                version
+            );
+            for (format, body) in [
+                (Format::Toml, toml),
+                (Format::Yaml, yaml),
+                (Format::Json, json),
+            ] {
+                let report = validate(format, &body, &Policy::default());
+                let invalid = report
+                    .issues
+                    .iter()
+                    .any(|issue| issue.code == "invalid_rust_version");
+                assert_eq!(!invalid, valid, "{:?} {}", format, version);
+            }
+        }
+    }
+
+    #[test]
+    fn parse_errors_have_locations() {
+        let toml = validate(
+            Format::Toml,
+            "[package]\nname = \"x\"\nkeywords = [\n",
+            &Policy::default(),
+        );
+        assert_eq!(codes(&toml), vec![("parse_error", Severity::Error)]);
+        assert_eq!(toml.issues[0].line, Some(4));
+
+        let json = validate(
+            Format::Json,
+            "{\"package\": {\n  \"name\": }\n}",
+            &Policy::default(),
+        );
+        assert_eq!(
+            (json.issues[0].line, json.issues[0].column),
+            (Some(2), Some(11))
+        );
+
+        let yaml = validate(
+            Format::Yaml,
+            "package:\n  name: x\n   keywords: [\n",
+            &Policy::default(),
+        );
+        assert_eq!(yaml.issues[0].code, "parse_error");
+        assert_eq!(yaml.issues[0].line, Some(3));
+    }
+
+    #[test]
+    fn schema_errors_are_reported() {
+        let body = r#"{"package": {"keywords": ["Christmas 2024"]}}"#;
+        let report = validate(Format::Json, body, &Policy::default());
+        assert_eq!(report.issues[0].code, "invalid_manifest");
+        assert!(report.issues[0].message.contains("name"));
+    }
+
+    #[test]
+    fn line_columns_count_characters() {
+        assert_eq!(line_column("ab\ncé\nd", 0), (1, 1));
+        assert_eq!(line_column("ab\ncé\nd", 3), (2, 1));
+        assert_eq!(line_column("ab\ncé\nd", 6), (2, 3));
+        assert_eq!(line_column("ab\ncé\nd", 7), (3, 1));
+    }
+}
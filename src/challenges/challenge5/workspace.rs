@@ -0,0 +1,448 @@
+use axum::{
+    extract::{Query, State},
+    http::HeaderMap,
+    response::{Response, Result},
+};
+use axum_extra::extract::Multipart;
+use cargo_manifest::Manifest;
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use serde::Deserialize;
+use serde_json::Value;
+use std::sync::Arc;
+
+use super::{reported_orders, respond, rust_version, validate, AppError, AppState, Format};
+
+/// `[package]` keys a member may take from `[workspace.package]`, as Cargo
+/// allows.
+const INHERITABLE: &[&str] = &[
+    "authors",
+    "categories",
+    "description",
+    "documentation",
+    "edition",
+    "exclude",
+    "homepage",
+    "include",
+    "keywords",
+    "license",
+    "license-file",
+    "publish",
+    "readme",
+    "repository",
+    "rust-version",
+    "version",
+];
+
+#[derive(Deserialize)]
+pub(super) struct WorkspaceParams {
+    /// Report fractional quantities instead of skipping them.
+    #[serde(default)]
+    fractional: bool,
+    /// Named policy to check every member against, instead of the default.
+    policy: Option<String>,
+}
+
+/// One manifest of the upload, named after its form field.
+#[derive(Debug)]
+struct Part {
+    name: String,
+    document: Value,
+}
+
+/// The format of a part by its Content-Type, then its file extension, and
+/// TOML when neither says, as a `Cargo.toml` is.
+fn part_format(content_type: Option<&str>, file_name: Option<&str>) -> Format {
+    if let Some(format) = content_type.and_then(Format::from_content_type) {
+        return format;
+    }
+    match file_name.and_then(|name| name.rsplit_once('.')) {
+        Some((_, "yaml" | "yml")) => Format::Yaml,
+        Some((_, "json")) => Format::Json,
+        _ => Format::Toml,
+    }
+}
+
+async fn read_parts(mut multipart: Multipart) -> Result<Vec<Part>, AppError> {
+    let invalid_upload = |e: &dyn std::fmt::Display| AppError::InvalidUpload(e.to_string());
+    let mut parts = vec![];
+    while let Some(field) = multipart
+        .next_field()
+        .await
+        .map_err(|e| invalid_upload(&e))?
+    {
+        let name = field.name().unwrap_or_default().to_string();
+        let format = part_format(field.content_type(), field.file_name());
+        let body = field.text().await.map_err(|e| invalid_upload(&e))?;
+        if format == Format::Toml && Manifest::from_slice(body.as_bytes()).is_err() {
+            return Err(AppError::InvalidManifest);
+        }
+        let document = validate::parse(format, &body).map_err(|_| AppError::InvalidManifest)?;
+        parts.push(Part { name, document });
+    }
+    Ok(parts)
+}
+
+/// Replace every `key.workspace = true` in a member's `[package]` with the
+/// value of `key` in `[workspace.package]`.
+fn inherit(member: &str, package: &mut Value, inherited: Option<&Value>) -> Result<(), AppError> {
+    let Some(package) = package.as_object_mut() else {
+        return Ok(());
+    };
+    for (key, value) in package.iter_mut() {
+        if value.get("workspace") != Some(&Value::Bool(true)) {
+            continue;
+        }
+        if !INHERITABLE.contains(&key.as_str()) {
+            return Err(AppError::NotInheritable {
+                member: member.to_string(),
+                key: key.clone(),
+            });
+        }
+        *value = inherited
+            .and_then(|inherited| inherited.get(key))
+            .ok_or_else(|| AppError::MissingInherited {
+                member: member.to_string(),
+                key: key.clone(),
+            })?
+            .clone();
+    }
+    Ok(())
+}
+
+/// Where a part sits in the workspace: its name without the manifest's file
+/// name, so `elves/Cargo.toml` is the member `elves`.
+fn member_path(name: &str) -> &str {
+    let name = name.trim_start_matches("./");
+    match name.rsplit_once('/') {
+        Some((dir, file)) if file.contains('.') => dir,
+        None if name.contains('.') => "",
+        _ => name.trim_end_matches('/'),
+    }
+}
+
+/// The paths listed under `key` in `[workspace]`.
+fn listed<'a>(workspace: &'a Value, key: &str) -> Result<Vec<&'a str>, AppError> {
+    let Some(paths) = workspace.get(key) else {
+        return Ok(vec![]);
+    };
+    paths
+        .as_array()
+        .ok_or(AppError::InvalidManifest)?
+        .iter()
+        .map(|path| {
+            let path = path.as_str().ok_or(AppError::InvalidManifest)?;
+            Ok(path.trim_start_matches("./").trim_end_matches('/'))
+        })
+        .collect()
+}
+
+/// The member paths `[workspace]` lists, globs as Cargo reads them, less
+/// those it excludes.
+struct Membership<'a> {
+    members: Vec<&'a str>,
+    globs: GlobSet,
+    exclude: Vec<&'a str>,
+}
+
+impl<'a> Membership<'a> {
+    fn new(workspace: &'a Value) -> Result<Self, AppError> {
+        let members = listed(workspace, "members")?;
+        let mut globs = GlobSetBuilder::new();
+        for member in &members {
+            let glob = GlobBuilder::new(member)
+                .literal_separator(true)
+                .build()
+                .map_err(|_| AppError::InvalidManifest)?;
+            globs.add(glob);
+        }
+        Ok(Membership {
+            globs: globs.build().map_err(|_| AppError::InvalidManifest)?,
+            members,
+            exclude: listed(workspace, "exclude")?,
+        })
+    }
+
+    fn includes(&self, path: &str) -> bool {
+        let excluded = self.exclude.iter().any(|exclude| {
+            path.strip_prefix(exclude)
+                .is_some_and(|rest| rest.is_empty() || rest.starts_with('/'))
+        });
+        self.globs.is_match(path) && !excluded
+    }
+
+    /// Members listed by path, not glob, that are not among `uploaded`.
+    fn missing(&self, uploaded: &[&str]) -> Option<&'a str> {
+        self.members
+            .iter()
+            .filter(|member| !member.contains(['*', '?', '[', '{']))
+            .find(|member| self.includes(member) && !uploaded.contains(member))
+            .copied()
+    }
+}
+
+/// A member's package name and its `[package]`.
+type Member = (String, Value);
+
+/// The `[package]` of every member, root included if it is a package, with
+/// inherited keys resolved, and the name of the root package if any. Every
+/// other part must be a member the root lists, and every member it lists by
+/// path must be uploaded.
+fn members(parts: Vec<Part>) -> Result<(Option<String>, Vec<Member>), AppError> {
+    let mut roots = parts
+        .iter()
+        .filter(|part| part.document.get("workspace").is_some());
+    let root = match (roots.next(), roots.next()) {
+        (Some(root), None) => root,
+        _ => return Err(AppError::NoWorkspaceRoot),
+    };
+    let workspace = root.document["workspace"].clone();
+    let membership = Membership::new(&workspace)?;
+    let uploaded: Vec<_> = parts
+        .iter()
+        .filter(|part| part.document.get("workspace").is_none())
+        .map(|part| member_path(&part.name))
+        .collect();
+    if let Some(missing) = membership.missing(&uploaded) {
+        return Err(AppError::MissingMember(missing.to_string()));
+    }
+    let inherited = root.document.pointer("/workspace/package").cloned();
+    let root_name = root
+        .document
+        .pointer("/package/name")
+        .and_then(Value::as_str)
+        .map(str::to_string);
+
+    let mut members = vec![];
+    for part in parts {
+        let is_root = part.document.get("workspace").is_some();
+        let Some(mut package) = part.document.get("package").cloned() else {
+            if is_root {
+                continue;
+            }
+            return Err(AppError::NotAPackage(part.name));
+        };
+        if !is_root && !membership.includes(member_path(&part.name)) {
+            return Err(AppError::NotAMember(part.name));
+        }
+        let label = package
+            .get("name")
+            .and_then(Value::as_str)
+            .unwrap_or(&part.name)
+            .to_string();
+        if members.iter().any(|(name, _)| *name == label) {
+            return Err(AppError::DuplicatePackage(label));
+        }
+        inherit(&label, &mut package, inherited.as_ref())?;
+        members.push((label, package));
+    }
+    Ok((root_name, members))
+}
+
+/// `POST /5/workspace`: a workspace root and its member manifests as parts of
+/// a multipart upload, in any mix of formats. Every member is checked as
+/// `/5/manifest` checks a package, and their orders reported together, each
+/// labelled with its member.
+pub(super) async fn workspace(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<WorkspaceParams>,
+    headers: HeaderMap,
+    multipart: Multipart,
+) -> Result<Response, AppError> {
+    let policy = state
+        .policies
+        .get(params.policy.as_deref())
+        .ok_or_else(|| AppError::UnknownPolicy(params.policy.clone().unwrap_or_default()))?;
+    let (root_name, members) = members(read_parts(multipart).await?)?;
+
+    let mut reported = vec![];
+    for (member, package) in members {
+        rust_version::read(&package).map_err(|_| AppError::InvalidManifest)?;
+        if let Some(mut violation) = policy.violations(&package).into_iter().next() {
+            violation.message = format!("{}: {}", member, violation.message);
+            return Err(AppError::PolicyViolation(violation));
+        }
+        reported.extend(reported_orders(&package, Some(&member), params.fractional)?);
+    }
+    if reported.is_empty() {
+        return Err(AppError::NoContent);
+    }
+    respond(&headers, root_name, reported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        body::Body,
+        extract::{FromRequest, Request},
+        http::header::CONTENT_TYPE,
+    };
+    use serde_json::json;
+
+    fn part(name: &str, document: Value) -> Part {
+        Part {
+            name: name.to_string(),
+            document,
+        }
+    }
+
+    #[test]
+    fn members_inherit_from_the_workspace() {
+        let parts = vec![
+            part(
+                "Cargo.toml",
+                json!({"workspace": {
+                    "members": ["elves"],
+                    "package": {"keywords": ["Christmas 2024"], "rust-version": "1.80"},
+                }}),
+            ),
+            part(
+                "elves/Cargo.toml",
+                json!({"package": {
+                    "name": "elves",
+                    "keywords": {"workspace": true},
+                    "rust-version": "1.83",
+                }}),
+            ),
+        ];
+        let (root_name, members) = members(parts).unwrap();
+        assert_eq!(root_name, None);
+        assert_eq!(
+            members,
+            vec![(
+                "elves".to_string(),
+                json!({"name": "elves", "keywords": ["Christmas 2024"], "rust-version": "1.83"})
+            )]
+        );
+    }
+
+    #[test]
+    fn inheritance_follows_cargo() {
+        let inherited = json!({"keywords": ["Christmas 2024"]});
+        let mut package = json!({"edition": {"workspace": true}});
+        assert!(matches!(
+            inherit("elves", &mut package, Some(&inherited)),
+            Err(AppError::MissingInherited { .. })
+        ));
+        let mut package = json!({"metadata": {"workspace": true}});
+        assert!(matches!(
+            inherit("elves", &mut package, Some(&inherited)),
+            Err(AppError::NotInheritable { .. })
+        ));
+    }
+
+    #[test]
+    fn a_workspace_has_one_root() {
+        let member = || part("elves", json!({"package": {"name": "elves"}}));
+        let root = || part("root", json!({"workspace": {}}));
+        assert!(matches!(
+            members(vec![member()]),
+            Err(AppError::NoWorkspaceRoot)
+        ));
+        assert!(matches!(
+            members(vec![root(), root(), member()]),
+            Err(AppError::NoWorkspaceRoot)
+        ));
+        assert!(matches!(
+            members(vec![root(), part("reindeer", json!({}))]),
+            Err(AppError::NotAPackage(name)) if name == "reindeer"
+        ));
+    }
+
+    #[test]
+    fn members_are_the_ones_the_root_lists() {
+        let root = || {
+            part(
+                "Cargo.toml",
+                json!({"workspace": {
+                    "members": ["elves", "reindeer/*"],
+                    "exclude": ["reindeer/rudolph"],
+                }}),
+            )
+        };
+        let member = |path: &str, name: &str| {
+            part(
+                &format!("{}/Cargo.toml", path),
+                json!({"package": {"name": name}}),
+            )
+        };
+        let (_, listed) = members(vec![
+            root(),
+            member("elves", "elves"),
+            member("reindeer/dasher", "dasher"),
+        ])
+        .unwrap();
+        assert_eq!(listed.len(), 2);
+
+        assert!(matches!(
+            members(vec![root(), member("reindeer/dasher", "dasher")]),
+            Err(AppError::MissingMember(path)) if path == "elves"
+        ));
+        assert!(matches!(
+            members(vec![root(), member("elves", "elves"), member("grinch", "grinch")]),
+            Err(AppError::NotAMember(name)) if name == "grinch/Cargo.toml"
+        ));
+        assert!(matches!(
+            members(vec![
+                root(),
+                member("elves", "elves"),
+                member("reindeer/rudolph", "rudolph"),
+            ]),
+            Err(AppError::NotAMember(name)) if name == "reindeer/rudolph/Cargo.toml"
+        ));
+        assert!(matches!(
+            members(vec![
+                root(),
+                member("elves", "elves"),
+                member("reindeer/elves", "elves"),
+            ]),
+            Err(AppError::DuplicatePackage(name)) if name == "elves"
+        ));
+    }
+
+    #[tokio::test]
+    async fn parts_come_from_a_multipart_upload() {
+        let upload = |body: &'static str| {
+            let request = Request::builder()
+                .header(CONTENT_TYPE, "multipart/form-data; boundary=sleigh")
+                .body(Body::from(body))
+                .unwrap();
+            async { read_parts(Multipart::from_request(request, &()).await.unwrap()).await }
+        };
+        let parts = upload(concat!(
+            "--sleigh\r\n",
+            "Content-Disposition: form-data; name=\"Cargo.toml\"\r\n\r\n",
+            "[workspace]\nmembers = [\"elves\"]\n\r\n",
+            "--sleigh\r\n",
+            "Content-Disposition: form-data; name=\"elves/Cargo.yaml\"; filename=\"Cargo.yaml\"\r\n\r\n",
+            "package:\n  name: elves\n\r\n",
+            "--sleigh--\r\n",
+        ))
+        .await
+        .unwrap();
+        let names: Vec<_> = parts.iter().map(|part| part.name.as_str()).collect();
+        assert_eq!(names, vec!["Cargo.toml", "elves/Cargo.yaml"]);
+        assert_eq!(parts[1].document, json!({"package": {"name": "elves"}}));
+        let (_, members) = members(parts).unwrap();
+        assert_eq!(members[0].0, "elves");
+
+        let invalid = upload(concat!(
+            "--sleigh\r\n",
+            "Content-Disposition: form-data; name=\"Cargo.toml\"\r\n\r\n",
+            "[workspace\r\n",
+            "--sleigh--\r\n",
+        ));
+        assert!(matches!(invalid.await, Err(AppError::InvalidManifest)));
+    }
+
+    #[test]
+    fn parts_are_read_by_type_then_extension() {
+        assert_eq!(part_format(Some("application/json"), None), Format::Json);
+        assert_eq!(
+            part_format(Some("application/octet-stream"), Some("elves.yml")),
+            Format::Yaml
+        );
+        assert_eq!(part_format(None, Some("Cargo.toml")), Format::Toml);
+        assert_eq!(part_format(None, None), Format::Toml);
+    }
+}
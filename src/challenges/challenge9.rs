@@ -1,42 +1,149 @@
 use axum::RequestExt;
 use axum::{
-    extract::{Request, State},
+    body::Bytes,
+    extract::{Form, Path, Query, Request, State},
     http::{HeaderMap, StatusCode},
     response::{IntoResponse, Response, Result},
-    routing::post,
+    routing::{get, post},
     Json, Router,
 };
+use chrono::{DateTime, Utc};
 use leaky_bucket::RateLimiter;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::{ops::DerefMut, sync::Arc, time::Duration};
+use shuttle_runtime::SecretStore;
+use sqlx::{FromRow, PgPool};
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 use thiserror::Error;
-use tokio::sync::Mutex;
 
-type MilkBucket = Arc<Mutex<RateLimiter>>;
+use policy::{Algorithm, LeakyBucket, Policy, RateLimitPolicy};
+use precision::{NumberFormat, Rounding};
+
+mod policy;
+mod precision;
+use tokio::{
+    sync::{Mutex, RwLock},
+    time::Instant,
+};
 
+const BUCKET_NAME: &str = "milk";
 const BUCKET_INITIAL: usize = 5;
 const BUCKET_CAPACITY: usize = 5;
 const BUCKET_REFILL_RATE: usize = 1;
 
+/// Secret selecting where the milk bucket keeps its tokens: `memory` (the
+/// default) or `postgres`.
+const BUCKET_BACKEND_SECRET: &str = "MILK_BUCKET_BACKEND";
+
+/// Secret selecting the milk bucket's [`Algorithm`], `leaky_bucket` by default.
+const BUCKET_ALGORITHM_SECRET: &str = "MILK_BUCKET_ALGORITHM";
+
+/// Upper bound on how long a caller may queue for milk.
+const MAX_WAIT: Duration = Duration::from_secs(30);
+
+/// Most tokens a bucket may hold or gain per refill, which keeps every
+/// bucket's numbers well within what Postgres and the policies can store.
+const MAX_BUCKET_TOKENS: usize = 1_000_000;
+
+/// Longest refill interval or window a bucket may have.
+const MAX_BUCKET_INTERVAL: Duration = Duration::from_secs(7 * 24 * 60 * 60);
+
+#[derive(Debug, Clone)]
+struct AppState {
+    buckets: BucketRegistry,
+    ledger: Ledger,
+}
+
 #[derive(Error, Debug)]
 enum AppError {
     #[error("Failed to parse JSON: {0}")]
     JsonParseError(#[from] serde_json::Error),
 
-    #[error("Missing Content-Type header")]
-    MissingContentType,
+    #[error("Invalid conversion: {0}")]
+    InvalidConversion(String),
+
+    #[error("Invalid number format: {0}")]
+    InvalidFormat(String),
+
+    #[error("Batch of {0} exceeds bucket capacity of {1}")]
+    BatchTooLarge(usize, usize),
 
     #[error("Too many requests")]
-    TooManyRequests,
+    TooManyRequests(String),
+
+    #[error("Database error: {0}")]
+    DatabaseError(#[from] sqlx::Error),
+
+    #[error("Invalid time window: {0}")]
+    InvalidWindow(String),
+
+    #[error("Invalid wait: {0}")]
+    InvalidWait(String),
+
+    #[error("Unknown bucket: {0}")]
+    UnknownBucket(String),
+
+    #[error("Bucket already exists: {0}")]
+    BucketExists(String),
+
+    #[error("Invalid bucket: {0}")]
+    InvalidBucket(String),
 }
 
 impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         let (status, error_message) = match self {
-            AppError::JsonParseError(_) => (StatusCode::NO_CONTENT, "Failed to parse JSON"),
-            AppError::MissingContentType => (StatusCode::BAD_REQUEST, "Invalid Content-Type"),
-            AppError::TooManyRequests => (StatusCode::TOO_MANY_REQUESTS, "No milk available\n"),
+            AppError::DatabaseError(e) => {
+                eprintln!("Database error: {:?}", e);
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Database error".to_string(),
+                )
+            }
+            AppError::JsonParseError(_) => {
+                (StatusCode::NO_CONTENT, "Failed to parse JSON".to_string())
+            }
+            AppError::InvalidConversion(reason) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid conversion: {}", reason),
+            ),
+            AppError::InvalidFormat(reason) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid number format: {}", reason),
+            ),
+            AppError::BatchTooLarge(size, capacity) => (
+                StatusCode::PAYLOAD_TOO_LARGE,
+                format!("Batch of {} exceeds bucket capacity of {}", size, capacity),
+            ),
+            AppError::TooManyRequests(resource) => (
+                StatusCode::TOO_MANY_REQUESTS,
+                format!("No {} available\n", resource),
+            ),
+            AppError::InvalidWindow(window) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid time window: {}", window),
+            ),
+            AppError::InvalidWait(wait) => {
+                (StatusCode::BAD_REQUEST, format!("Invalid wait: {}", wait))
+            }
+            AppError::UnknownBucket(name) => {
+                (StatusCode::NOT_FOUND, format!("Unknown bucket: {}", name))
+            }
+            AppError::BucketExists(name) => (
+                StatusCode::CONFLICT,
+                format!("Bucket already exists: {}", name),
+            ),
+            AppError::InvalidBucket(reason) => (
+                StatusCode::BAD_REQUEST,
+                format!("Invalid bucket: {}", reason),
+            ),
         };
 
         (status, error_message).into_response()
@@ -52,62 +159,1592 @@ enum Unit {
     Pints(f64),
 }
 
+impl Unit {
+    /// Query parameters read as `unit=amount` conversions.
+    const NAMES: [&'static str; 4] = ["liters", "litres", "gallons", "pints"];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Unit::Liters(_) => "liters",
+            Unit::Litres(_) => "litres",
+            Unit::Gallons(_) => "gallons",
+            Unit::Pints(_) => "pints",
+        }
+    }
+
+    fn convert(&self, format: &NumberFormat) -> Result<serde_json::Value, String> {
+        let (target, payload, factor) = match self {
+            Unit::Liters(payload) => ("gallons", payload, "0.264172"),
+            Unit::Litres(payload) => ("pints", payload, "1.75975"),
+            Unit::Gallons(payload) => ("liters", payload, "3.78541"),
+            Unit::Pints(payload) => ("litres", payload, "0.568261"),
+        };
+        Ok(json!({ target: format.convert(*payload, factor)? }))
+    }
+}
+
+/// Conversions requested alongside a withdrawal.
+#[derive(Debug)]
+enum Conversions {
+    /// One conversion, answered with a single object.
+    Single(Unit),
+    /// Several conversions, answered with one result per item, in order.
+    Batch(Vec<Result<Unit, String>>),
+}
+
+impl Conversions {
+    /// A single conversion object, or an array of them.
+    fn from_value(value: serde_json::Value) -> Result<Self, AppError> {
+        match value {
+            serde_json::Value::Array(items) if items.is_empty() => {
+                Err(AppError::InvalidConversion("empty batch".to_string()))
+            }
+            serde_json::Value::Array(items) => Ok(Conversions::Batch(
+                items
+                    .into_iter()
+                    .map(|item| serde_json::from_value(item).map_err(|e| e.to_string()))
+                    .collect(),
+            )),
+            value => serde_json::from_value(value)
+                .map(Conversions::Single)
+                .map_err(|e| AppError::InvalidConversion(e.to_string())),
+        }
+    }
+
+    /// `unit=amount` pairs from a form body or the query string. A single
+    /// pair is a single conversion.
+    fn from_pairs(pairs: Vec<(String, String)>) -> Result<Self, AppError> {
+        let mut items: Vec<_> = pairs
+            .into_iter()
+            .map(|(name, amount)| {
+                let value = amount
+                    .trim()
+                    .parse::<f64>()
+                    .ok()
+                    .filter(|value| value.is_finite())
+                    .ok_or_else(|| format!("invalid amount for {}: {}", name, amount))?;
+                let item = serde_json::Value::Object([(name, json!(value))].into_iter().collect());
+                serde_json::from_value(item).map_err(|e| e.to_string())
+            })
+            .collect();
+        match items.len() {
+            0 => Err(AppError::InvalidConversion("empty batch".to_string())),
+            1 => items
+                .pop()
+                .unwrap()
+                .map(Conversions::Single)
+                .map_err(AppError::InvalidConversion),
+            _ => Ok(Conversions::Batch(items)),
+        }
+    }
+
+    /// Work out every result up front, so a request is only charged for
+    /// once its response is known.
+    fn convert(self, format: &NumberFormat) -> Result<Converted, AppError> {
+        match self {
+            Conversions::Single(unit) => Ok(Converted {
+                units: vec![Some(unit.name())],
+                body: unit.convert(format).map_err(AppError::InvalidConversion)?,
+            }),
+            Conversions::Batch(items) => {
+                let (units, results) = items
+                    .into_iter()
+                    .map(|item| {
+                        match item.and_then(|unit| Ok((unit.name(), unit.convert(format)?))) {
+                            Ok((unit, result)) => (Some(unit), result),
+                            Err(e) => (None, json!({ "error": e })),
+                        }
+                    })
+                    .unzip();
+                Ok(Converted {
+                    units,
+                    body: serde_json::Value::Array(results),
+                })
+            }
+        }
+    }
+}
+
+/// Conversion results, with the unit of each item, or `None` if it was
+/// invalid. Each item costs one token, valid or not.
+#[derive(Debug)]
+struct Converted {
+    units: Vec<Option<&'static str>>,
+    body: serde_json::Value,
+}
+
+/// Read the optional conversions from a JSON, YAML or form-encoded body,
+/// falling back to `unit=amount` pairs in the query string, where any other
+/// parameter is ignored. A request with neither is a plain withdrawal.
+async fn read_conversions(
+    headers: &HeaderMap,
+    req: Request,
+) -> Option<Result<Conversions, AppError>> {
+    let query: Vec<(String, String)> = Query::try_from_uri(req.uri())
+        .map(|Query(pairs): Query<Vec<(String, String)>>| pairs)
+        .unwrap_or_default()
+        .into_iter()
+        .filter(|(name, _)| Unit::NAMES.contains(&name.as_str()))
+        .collect();
+    let media_type = headers
+        .get(axum::http::header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .map(|value| value.trim().to_ascii_lowercase());
+
+    let conversions = match media_type.as_deref() {
+        Some("application/json") => match req.extract::<Bytes, _>().await {
+            Ok(body) => serde_json::from_slice(&body)
+                .map_err(|e| AppError::InvalidConversion(e.to_string()))
+                .and_then(Conversions::from_value),
+            Err(e) => Err(AppError::InvalidConversion(e.body_text())),
+        },
+        Some("application/yaml" | "application/x-yaml" | "text/yaml") => {
+            match req.extract::<Bytes, _>().await {
+                Ok(body) => serde_yaml::from_slice(&body)
+                    .map_err(|e| AppError::InvalidConversion(e.to_string()))
+                    .and_then(Conversions::from_value),
+                Err(e) => Err(AppError::InvalidConversion(e.body_text())),
+            }
+        }
+        Some("application/x-www-form-urlencoded") => {
+            match req.extract::<Form<Vec<(String, String)>>, _>().await {
+                Ok(Form(pairs)) if pairs.is_empty() => return None,
+                Ok(Form(pairs)) => Conversions::from_pairs(pairs),
+                Err(e) => Err(AppError::InvalidConversion(e.body_text())),
+            }
+        }
+        _ if !query.is_empty() => Conversions::from_pairs(query),
+        _ => return None,
+    };
+    Some(conversions)
+}
+
+#[derive(Deserialize)]
+struct MilkParams {
+    wait: Option<String>,
+    precision: Option<u32>,
+    significant_figures: Option<u32>,
+    rounding: Option<Rounding>,
+    #[serde(default)]
+    exact: bool,
+}
+
+impl MilkParams {
+    fn number_format(&self) -> Result<NumberFormat, AppError> {
+        NumberFormat::new(
+            self.precision,
+            self.significant_figures,
+            self.rounding,
+            self.exact,
+        )
+        .map_err(AppError::InvalidFormat)
+    }
+}
+
+/// How long the caller is willing to queue for milk, from `?wait=2s` or an
+/// RFC 7240 `Prefer: wait=2` header. The query parameter takes precedence.
+fn requested_wait(params: &MilkParams, headers: &HeaderMap) -> Result<Option<Duration>, AppError> {
+    let prefer_wait = headers
+        .get_all("prefer")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split([',', ';']))
+        .find_map(|preference| {
+            let (name, value) = preference.split_once('=')?;
+            name.trim()
+                .eq_ignore_ascii_case("wait")
+                .then(|| value.trim().trim_matches('"'))
+        });
+    let Some(wait) = params.wait.as_deref().or(prefer_wait) else {
+        return Ok(None);
+    };
+    let wait = parse_duration(wait).ok_or_else(|| AppError::InvalidWait(wait.to_string()))?;
+    Ok(Some(wait.min(MAX_WAIT)))
+}
+
 async fn get_milk(
-    State(bucket): State<MilkBucket>,
+    state: State<Arc<AppState>>,
+    params: Query<MilkParams>,
     headers: HeaderMap,
     req: Request,
 ) -> Result<String, AppError> {
-    let got_milk = bucket.lock().await.try_acquire(1);
-    if !got_milk {
-        return Err(AppError::TooManyRequests);
+    withdraw(state, Path(BUCKET_NAME.to_string()), params, headers, req).await
+}
+
+async fn withdraw(
+    State(state): State<Arc<AppState>>,
+    Path(resource): Path<String>,
+    Query(params): Query<MilkParams>,
+    headers: HeaderMap,
+    req: Request,
+) -> Result<String, AppError> {
+    let bucket = state.buckets.get(&resource).await?;
+    let client = client_key(&headers);
+    let wait = requested_wait(&params, &headers)?;
+    let format = params.number_format()?;
+    let conversions = read_conversions(&headers, req)
+        .await
+        .map(|conversions| conversions.and_then(|conversions| conversions.convert(&format)));
+    let (permits, units) = match &conversions {
+        Some(Ok(converted)) => (converted.units.len(), converted.units.clone()),
+        _ => (1, vec![None]),
+    };
+    let capacity = bucket.config().capacity;
+    if permits > capacity {
+        return Err(AppError::BatchTooLarge(permits, capacity));
+    }
+
+    let got_resource = match wait {
+        Some(wait) => bucket.acquire_within(permits, wait).await?,
+        None => bucket.try_acquire(permits).await?,
+    };
+    let record = |outcome: fn(Option<&str>) -> Outcome| {
+        let entries = units.iter().map(|&unit| (unit, outcome(unit))).collect();
+        state.ledger.record(&resource, &client, entries);
+    };
+    if !got_resource {
+        record(|_| Outcome::Rejected);
+        return Err(AppError::TooManyRequests(resource));
+    }
+    match conversions {
+        None => {
+            record(|_| Outcome::Withdrawn);
+            Ok(format!("{} withdrawn\n", capitalize(&resource)))
+        }
+        Some(Ok(converted)) => {
+            record(|unit| match unit {
+                Some(_) => Outcome::Withdrawn,
+                None => Outcome::Invalid,
+            });
+            Ok(converted.body.to_string())
+        }
+        Some(Err(e)) => {
+            record(|_| Outcome::Invalid);
+            Err(e)
+        }
+    }
+}
+
+fn capitalize(name: &str) -> String {
+    let mut chars = name.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BucketConfig {
+    initial: usize,
+    capacity: usize,
+    refill: usize,
+    interval: Duration,
+    algorithm: Algorithm,
+}
+
+impl BucketConfig {
+    fn validate(&self) -> Result<(), AppError> {
+        if self.capacity == 0 {
+            return Err(AppError::InvalidBucket(
+                "capacity must be at least 1".to_string(),
+            ));
+        }
+        if self.initial > self.capacity {
+            return Err(AppError::InvalidBucket(
+                "initial must not exceed capacity".to_string(),
+            ));
+        }
+        if self.refill == 0 || self.interval.is_zero() {
+            return Err(AppError::InvalidBucket(
+                "refill and interval_ms must be at least 1".to_string(),
+            ));
+        }
+        if self.capacity > MAX_BUCKET_TOKENS || self.refill > MAX_BUCKET_TOKENS {
+            return Err(AppError::InvalidBucket(format!(
+                "capacity and refill must not exceed {}",
+                MAX_BUCKET_TOKENS
+            )));
+        }
+        if self.interval > MAX_BUCKET_INTERVAL {
+            return Err(AppError::InvalidBucket(format!(
+                "interval_ms must not exceed {}",
+                MAX_BUCKET_INTERVAL.as_millis()
+            )));
+        }
+        Ok(())
     }
-    let content_type = headers.get(axum::http::header::CONTENT_TYPE);
-    if content_type == Some(&"application/json".parse().unwrap()) {
-        let Json(payload) = match req.extract::<Json<Unit>, _>().await {
-            Ok(payload) => payload,
-            Err(e) => {
-                println!("Failed to parse JSON: {}", e);
+}
+
+const MILK_BUCKET_CONFIG: BucketConfig = BucketConfig {
+    initial: BUCKET_INITIAL,
+    capacity: BUCKET_CAPACITY,
+    refill: 1,
+    interval: Duration::from_secs(BUCKET_REFILL_RATE as u64),
+    algorithm: Algorithm::LeakyBucket,
+};
+
+/// Time since the UNIX epoch, the clock every [`RateLimitPolicy`] runs on.
+fn since_epoch(time: DateTime<Utc>) -> Duration {
+    Duration::from_micros(time.timestamp_micros().max(0) as u64)
+}
+
+/// The inverse of [`since_epoch`].
+fn until_epoch(time: Duration) -> DateTime<Utc> {
+    DateTime::from_timestamp_micros(time.as_micros() as i64).unwrap_or_default()
+}
+
+/// In-process limiter for one bucket: the `leaky_bucket` crate's token bucket
+/// for [`Algorithm::LeakyBucket`], and a [`PolicyLimiter`] for the rest.
+#[derive(Debug)]
+enum Limiter {
+    LeakyBucket(LeakyBucketLimiter),
+    Policy(PolicyLimiter),
+}
 
-                return Err(AppError::MissingContentType);
+impl Limiter {
+    fn new(config: &BucketConfig, manually_refilled_at: Option<DateTime<Utc>>) -> Self {
+        match config.algorithm {
+            Algorithm::LeakyBucket => {
+                Limiter::LeakyBucket(LeakyBucketLimiter::new(config, manually_refilled_at))
             }
+            _ => Limiter::Policy(PolicyLimiter::new(config, manually_refilled_at)),
+        }
+    }
+
+    fn try_acquire(&self, permits: usize) -> bool {
+        match self {
+            Limiter::LeakyBucket(limiter) => limiter.limiter.try_acquire(permits),
+            Limiter::Policy(limiter) => limiter.try_acquire(permits),
+        }
+    }
+
+    /// Wait in line until the permits are granted. Dropping the future gives
+    /// up the place in the queue.
+    async fn acquire(&self, permits: usize) {
+        match self {
+            Limiter::LeakyBucket(limiter) => limiter.limiter.acquire(permits).await,
+            Limiter::Policy(limiter) => limiter.acquire(permits).await,
+        }
+    }
+
+    fn snapshot(&self) -> BucketSnapshot {
+        match self {
+            Limiter::LeakyBucket(limiter) => limiter.snapshot(),
+            Limiter::Policy(limiter) => limiter.snapshot(),
+        }
+    }
+}
+
+/// A fair [`RateLimiter`], which queues waiting callers in arrival order and
+/// turns away callers that don't wait while anyone is queued.
+#[derive(Debug)]
+struct LeakyBucketLimiter {
+    limiter: RateLimiter,
+    built_at: Instant,
+    manually_refilled_at: Option<DateTime<Utc>>,
+}
+
+impl LeakyBucketLimiter {
+    fn new(config: &BucketConfig, manually_refilled_at: Option<DateTime<Utc>>) -> Self {
+        LeakyBucketLimiter {
+            limiter: RateLimiter::builder()
+                .initial(config.initial)
+                .max(config.capacity)
+                .refill(config.refill)
+                .interval(config.interval)
+                .build(),
+            built_at: Instant::now(),
+            manually_refilled_at,
+        }
+    }
+
+    fn snapshot(&self) -> BucketSnapshot {
+        // `leaky_bucket` only tops its balance up when drawn from, and has no
+        // way to read it otherwise, so ask for more than it can ever hold. The
+        // balance never reaches `max + 1`, so nothing is taken; and a limiter
+        // with callers queued turns the probe away before it touches anything,
+        // so the queue is neither joined nor reordered. All the probe does is
+        // add the refills that are due, as the next caller would.
+        let max = self.limiter.max();
+        self.limiter.try_acquire(max.saturating_add(1));
+        let tokens = self.limiter.balance();
+        // Refills fall a whole number of intervals after the limiter was built.
+        let interval = self.limiter.interval();
+        let since_refill = self.built_at.elapsed().as_nanos() % interval.as_nanos();
+        BucketSnapshot {
+            tokens,
+            next_refill: (tokens < max)
+                .then(|| interval - Duration::from_nanos(since_refill as u64)),
+            manually_refilled_at: self.manually_refilled_at,
+        }
+    }
+}
+
+/// In-process limiter for the algorithms `leaky_bucket` doesn't provide.
+/// Callers that wait for permits queue on a fair mutex, and callers that don't
+/// are turned away while anyone is queued, so nobody jumps the line.
+#[derive(Debug)]
+struct PolicyLimiter {
+    policy: std::sync::Mutex<Policy>,
+    queue: Mutex<()>,
+    /// Callers queued in [`PolicyLimiter::acquire`].
+    waiting: AtomicUsize,
+    manually_refilled_at: Option<DateTime<Utc>>,
+}
+
+/// A place in a [`PolicyLimiter`]'s queue, given up when dropped.
+struct Waiting<'a>(&'a AtomicUsize);
+
+impl<'a> Waiting<'a> {
+    fn join(waiting: &'a AtomicUsize) -> Self {
+        waiting.fetch_add(1, Ordering::SeqCst);
+        Waiting(waiting)
+    }
+}
+
+impl Drop for Waiting<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl PolicyLimiter {
+    fn new(config: &BucketConfig, manually_refilled_at: Option<DateTime<Utc>>) -> Self {
+        PolicyLimiter {
+            policy: std::sync::Mutex::new(Policy::new(config, since_epoch(Utc::now()))),
+            queue: Mutex::new(()),
+            waiting: AtomicUsize::new(0),
+            manually_refilled_at,
+        }
+    }
+
+    /// Take the permits now, unless someone is waiting for theirs. Callers
+    /// that don't wait never turn each other away.
+    fn try_acquire(&self, permits: usize) -> bool {
+        if self.waiting.load(Ordering::SeqCst) > 0 {
+            return false;
+        }
+        self.admit(permits).is_ok()
+    }
+
+    /// Wait in line until the permits are granted. Dropping the future gives
+    /// up the place in the queue.
+    async fn acquire(&self, permits: usize) {
+        let _waiting = Waiting::join(&self.waiting);
+        let _turn = self.queue.lock().await;
+        loop {
+            match self.admit(permits) {
+                Ok(()) => return,
+                Err(Some(retry_after)) => tokio::time::sleep(retry_after).await,
+                Err(None) => std::future::pending().await,
+            }
+        }
+    }
+
+    /// Take the permits now, or say how long until they could be taken.
+    fn admit(&self, permits: usize) -> Result<(), Option<Duration>> {
+        let now = since_epoch(Utc::now());
+        let mut policy = self.policy.lock().unwrap();
+        if policy.try_acquire(permits, now) {
+            return Ok(());
+        }
+        Err(policy.retry_after(permits, now))
+    }
+
+    fn snapshot(&self) -> BucketSnapshot {
+        let now = since_epoch(Utc::now());
+        let policy = self.policy.lock().unwrap();
+        BucketSnapshot {
+            tokens: policy.available(now),
+            next_refill: policy.next_refill(now),
+            manually_refilled_at: self.manually_refilled_at,
+        }
+    }
+}
+
+/// Read-only view of a bucket at one instant.
+#[derive(Debug, Clone, Copy)]
+struct BucketSnapshot {
+    tokens: usize,
+    next_refill: Option<Duration>,
+    manually_refilled_at: Option<DateTime<Utc>>,
+}
+
+#[derive(FromRow, Debug)]
+struct PolicyRow {
+    state: Option<sqlx::types::Json<Policy>>,
+    tokens: i64,
+    refilled_at: DateTime<Utc>,
+    manually_refilled_at: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+}
+
+#[derive(FromRow, Debug)]
+struct BucketConfigRow {
+    name: String,
+    initial: i64,
+    capacity: i64,
+    refill_amount: i64,
+    refill_interval_ms: i64,
+    algorithm: String,
+}
+
+impl TryFrom<&BucketConfigRow> for BucketConfig {
+    type Error = AppError;
+    fn try_from(row: &BucketConfigRow) -> Result<Self, Self::Error> {
+        let invalid = |column: &str| AppError::InvalidBucket(format!("{} out of range", column));
+        let config = BucketConfig {
+            initial: row.initial.try_into().map_err(|_| invalid("initial"))?,
+            capacity: row.capacity.try_into().map_err(|_| invalid("capacity"))?,
+            refill: row
+                .refill_amount
+                .try_into()
+                .map_err(|_| invalid("refill_amount"))?,
+            interval: Duration::from_millis(
+                row.refill_interval_ms
+                    .try_into()
+                    .map_err(|_| invalid("refill_interval_ms"))?,
+            ),
+            algorithm: row.algorithm.parse().map_err(AppError::InvalidBucket)?,
         };
-        match payload {
-            Unit::Liters(payload) => {
-                return Ok(json!({"gallons": payload * 0.264172}).to_string());
+        config.validate()?;
+        Ok(config)
+    }
+}
+
+/// Rate limiter whose policy state lives in Postgres, so every replica of
+/// the service draws from the same supply.
+///
+/// Acquisitions lock the bucket row (`SELECT ... FOR UPDATE`) for the
+/// duration of a short transaction, which serialises concurrent callers across
+/// instances. Time comes from the database clock so replicas with skewed
+/// clocks still agree on when the bucket refills.
+///
+/// A leaky bucket is kept in the `tokens` and `refilled_at` columns rather
+/// than `state`, as the previous release keeps it, so instances of both
+/// releases draw from the same supply during a rolling deploy.
+#[derive(Debug, Clone)]
+struct PgRateLimiter {
+    pool: PgPool,
+    name: String,
+    config: BucketConfig,
+}
+
+impl PgRateLimiter {
+    fn new(pool: PgPool, name: &str, config: BucketConfig) -> Self {
+        Self {
+            pool,
+            name: name.to_string(),
+            config,
+        }
+    }
+
+    async fn try_acquire(&self, permits: usize) -> Result<bool, sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        let row = match self.lock_row(&mut transaction).await? {
+            Some(row) => row,
+            None => {
+                self.insert(&mut transaction).await?;
+                self.lock_row(&mut transaction)
+                    .await?
+                    .ok_or(sqlx::Error::RowNotFound)?
             }
-            Unit::Litres(payload) => {
-                return Ok(json!({"pints": payload * 1.75975}).to_string());
+        };
+
+        let (mut policy, now) = self.policy(row);
+        if !policy.try_acquire(permits, now) {
+            transaction.rollback().await?;
+            return Ok(false);
+        }
+        let columns = match &policy {
+            Policy::LeakyBucket(bucket) => Some(bucket.columns()),
+            _ => None,
+        };
+        sqlx::query(
+            "UPDATE rate_limiter_buckets
+                SET state = $2, tokens = COALESCE($3, tokens),
+                    refilled_at = COALESCE($4, refilled_at)
+                WHERE name = $1",
+        )
+        .bind(&self.name)
+        .bind(sqlx::types::Json(&policy))
+        .bind(columns.map(|(tokens, _)| tokens as i64))
+        .bind(columns.map(|(_, refilled_at)| until_epoch(refilled_at)))
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+
+        Ok(true)
+    }
+
+    /// Postgres has no queue to park on, so waiting callers poll the bucket
+    /// until the deadline. Unlike the in-memory bucket this is not fair.
+    async fn acquire_within(&self, permits: usize, wait: Duration) -> Result<bool, sqlx::Error> {
+        let deadline = Instant::now() + wait;
+        let poll_interval = (self.config.interval / 4).max(Duration::from_millis(10));
+        loop {
+            if self.try_acquire(permits).await? {
+                return Ok(true);
             }
-            Unit::Gallons(payload) => {
-                return Ok(json!({"liters": payload * 3.78541}).to_string());
+            let now = Instant::now();
+            if now >= deadline {
+                return Ok(false);
             }
-            Unit::Pints(payload) => {
-                return Ok(json!({"litres": payload * 0.568261}).to_string());
+            tokio::time::sleep(poll_interval.min(deadline - now)).await;
+        }
+    }
+
+    /// Reset the bucket; the policy starts afresh on its next use. `tokens`
+    /// and `refilled_at` are reset too, for instances of the previous release.
+    async fn refill(&self) -> Result<(), sqlx::Error> {
+        let mut transaction = self.pool.begin().await?;
+        self.insert(&mut transaction).await?;
+        sqlx::query(
+            "UPDATE rate_limiter_buckets
+                SET state = NULL, manually_refilled_at = now(),
+                    tokens = initial, refilled_at = now()
+                WHERE name = $1",
+        )
+        .bind(&self.name)
+        .execute(&mut *transaction)
+        .await?;
+        transaction.commit().await?;
+        Ok(())
+    }
+
+    /// Create the bucket row, returning `false` if it already exists. It starts
+    /// with `initial` tokens for instances of the previous release.
+    async fn insert(&self, connection: &mut sqlx::PgConnection) -> Result<bool, sqlx::Error> {
+        let result = sqlx::query(
+            "INSERT INTO rate_limiter_buckets
+                (name, initial, capacity, refill_amount, refill_interval_ms, algorithm, tokens)
+            VALUES ($1, $2, $3, $4, $5, $6, $2)
+            ON CONFLICT (name) DO NOTHING",
+        )
+        .bind(&self.name)
+        .bind(self.config.initial as i64)
+        .bind(self.config.capacity as i64)
+        .bind(self.config.refill as i64)
+        .bind(self.config.interval.as_millis() as i64)
+        .bind(self.config.algorithm.as_str())
+        .execute(connection)
+        .await?;
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Look at the bucket without taking anything or locking the row.
+    async fn snapshot(&self) -> Result<BucketSnapshot, sqlx::Error> {
+        let row = sqlx::query_as::<_, PolicyRow>(
+            "SELECT state, tokens, refilled_at, manually_refilled_at, now() AS now
+                FROM rate_limiter_buckets
+                WHERE name = $1",
+        )
+        .bind(&self.name)
+        .fetch_optional(&self.pool)
+        .await?;
+        let manually_refilled_at = row.as_ref().and_then(|row| row.manually_refilled_at);
+        let (policy, now) = match row {
+            Some(row) => self.policy(row),
+            None => {
+                let now = since_epoch(Utc::now());
+                (Policy::new(&self.config, now), now)
+            }
+        };
+        Ok(BucketSnapshot {
+            tokens: policy.available(now),
+            next_refill: policy.next_refill(now),
+            manually_refilled_at,
+        })
+    }
+
+    async fn lock_row(
+        &self,
+        transaction: &mut sqlx::PgConnection,
+    ) -> Result<Option<PolicyRow>, sqlx::Error> {
+        sqlx::query_as::<_, PolicyRow>(
+            "SELECT state, tokens, refilled_at, manually_refilled_at, now() AS now
+                FROM rate_limiter_buckets
+                WHERE name = $1 FOR UPDATE",
+        )
+        .bind(&self.name)
+        .fetch_optional(transaction)
+        .await
+    }
+
+    /// The stored policy, or a fresh one if the bucket hasn't been used since
+    /// it was created or refilled, along with the database's current time.
+    fn policy(&self, row: PolicyRow) -> (Policy, Duration) {
+        let now = since_epoch(row.now);
+        let policy = match (self.config.algorithm, row.state) {
+            (Algorithm::LeakyBucket, _) => Policy::LeakyBucket(LeakyBucket::from_columns(
+                &self.config,
+                row.tokens.max(0) as usize,
+                since_epoch(row.refilled_at),
+            )),
+            (_, Some(state)) => state.0,
+            (_, None) => Policy::new(&self.config, now),
+        };
+        (policy, now)
+    }
+}
+
+/// A resource bucket, either local to this process or shared through Postgres.
+#[derive(Debug, Clone)]
+enum Bucket {
+    InMemory {
+        limiter: Arc<RwLock<Arc<Limiter>>>,
+        config: BucketConfig,
+    },
+    Postgres(PgRateLimiter),
+}
+
+impl Bucket {
+    fn in_memory(config: BucketConfig) -> Self {
+        Bucket::InMemory {
+            limiter: Arc::new(RwLock::new(Arc::new(Limiter::new(&config, None)))),
+            config,
+        }
+    }
+
+    fn config(&self) -> &BucketConfig {
+        match self {
+            Bucket::InMemory { config, .. } => config,
+            Bucket::Postgres(bucket) => &bucket.config,
+        }
+    }
+
+    async fn try_acquire(&self, permits: usize) -> Result<bool, AppError> {
+        match self {
+            Bucket::InMemory { limiter, .. } => Ok(limiter.read().await.try_acquire(permits)),
+            Bucket::Postgres(bucket) => Ok(bucket.try_acquire(permits).await?),
+        }
+    }
+
+    /// Queue for up to `wait` for the permits. The in-memory limiter is fair,
+    /// so waiting callers are served in arrival order and callers that don't
+    /// wait cannot jump the queue.
+    async fn acquire_within(&self, permits: usize, wait: Duration) -> Result<bool, AppError> {
+        match self {
+            Bucket::InMemory { limiter, .. } => {
+                // Don't hold the lock while queueing, or a refill would have to
+                // wait for every queued caller.
+                let limiter = limiter.read().await.clone();
+                Ok(tokio::time::timeout(wait, limiter.acquire(permits))
+                    .await
+                    .is_ok())
             }
+            Bucket::Postgres(bucket) => Ok(bucket.acquire_within(permits, wait).await?),
+        }
+    }
+
+    async fn refill(&self) -> Result<(), AppError> {
+        match self {
+            Bucket::InMemory { limiter, config } => {
+                *limiter.write().await = Arc::new(Limiter::new(config, Some(Utc::now())))
+            }
+            Bucket::Postgres(bucket) => bucket.refill().await?,
+        }
+        Ok(())
+    }
+
+    /// Current state of the bucket. Never takes permits.
+    async fn snapshot(&self) -> Result<BucketSnapshot, AppError> {
+        match self {
+            Bucket::InMemory { limiter, .. } => Ok(limiter.read().await.snapshot()),
+            Bucket::Postgres(bucket) => Ok(bucket.snapshot().await?),
         }
     }
-    Ok("Milk withdrawn\n".to_string())
 }
 
-fn get_rate_limiter() -> RateLimiter {
-    RateLimiter::builder()
-        .initial(BUCKET_INITIAL)
-        .max(BUCKET_CAPACITY)
-        .interval(Duration::from_secs(BUCKET_REFILL_RATE as u64))
-        .build()
+/// Every named bucket the service hands out. The milk bucket always exists;
+/// the rest are created through `POST /9/buckets`.
+#[derive(Debug, Clone)]
+enum BucketRegistry {
+    InMemory(Arc<RwLock<HashMap<String, Bucket>>>),
+    Postgres { pool: PgPool, milk: BucketConfig },
 }
 
-async fn refill_bucket(State(bucket): State<MilkBucket>) -> Result<String, AppError> {
-    let mut lock = bucket.lock().await;
-    let bucket = lock.deref_mut();
-    *bucket = get_rate_limiter();
+impl BucketRegistry {
+    fn from_secrets(pool: PgPool, secrets: &SecretStore) -> Self {
+        let milk = BucketConfig {
+            algorithm: match secrets.get(BUCKET_ALGORITHM_SECRET) {
+                Some(algorithm) => algorithm
+                    .parse()
+                    .unwrap_or_else(|e| panic!("Invalid {BUCKET_ALGORITHM_SECRET}: {e}")),
+                None => MILK_BUCKET_CONFIG.algorithm,
+            },
+            ..MILK_BUCKET_CONFIG
+        };
+        match secrets.get(BUCKET_BACKEND_SECRET).as_deref() {
+            None | Some("memory") => {
+                let buckets = HashMap::from([(BUCKET_NAME.to_string(), Bucket::in_memory(milk))]);
+                BucketRegistry::InMemory(Arc::new(RwLock::new(buckets)))
+            }
+            Some("postgres") => BucketRegistry::Postgres { pool, milk },
+            Some(other) => panic!("Unknown {BUCKET_BACKEND_SECRET} value: {other}"),
+        }
+    }
+
+    async fn get(&self, name: &str) -> Result<Bucket, AppError> {
+        match self {
+            BucketRegistry::InMemory(buckets) => buckets
+                .read()
+                .await
+                .get(name)
+                .cloned()
+                .ok_or_else(|| AppError::UnknownBucket(name.to_string())),
+            BucketRegistry::Postgres { pool, milk } => {
+                let row = sqlx::query_as::<_, BucketConfigRow>(
+                    "SELECT name, initial, capacity, refill_amount, refill_interval_ms, algorithm
+                        FROM rate_limiter_buckets WHERE name = $1",
+                )
+                .bind(name)
+                .fetch_optional(pool)
+                .await?;
+                // The milk row is created lazily on first use.
+                let config = match row {
+                    Some(row) => BucketConfig::try_from(&row)?,
+                    None if name == BUCKET_NAME => *milk,
+                    None => return Err(AppError::UnknownBucket(name.to_string())),
+                };
+                Ok(Bucket::Postgres(PgRateLimiter::new(
+                    pool.clone(),
+                    name,
+                    config,
+                )))
+            }
+        }
+    }
+
+    async fn create(&self, name: &str, config: BucketConfig) -> Result<Bucket, AppError> {
+        match self {
+            BucketRegistry::InMemory(buckets) => {
+                let mut buckets = buckets.write().await;
+                if buckets.contains_key(name) {
+                    return Err(AppError::BucketExists(name.to_string()));
+                }
+                let bucket = Bucket::in_memory(config);
+                buckets.insert(name.to_string(), bucket.clone());
+                Ok(bucket)
+            }
+            BucketRegistry::Postgres { pool, .. } => {
+                let bucket = PgRateLimiter::new(pool.clone(), name, config);
+                let mut connection = pool.acquire().await?;
+                if !bucket.insert(&mut connection).await? {
+                    return Err(AppError::BucketExists(name.to_string()));
+                }
+                Ok(Bucket::Postgres(bucket))
+            }
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<(String, Bucket)>, AppError> {
+        let mut buckets = match self {
+            BucketRegistry::InMemory(buckets) => buckets
+                .read()
+                .await
+                .iter()
+                .map(|(name, bucket)| (name.clone(), bucket.clone()))
+                .collect::<Vec<_>>(),
+            BucketRegistry::Postgres { pool, .. } => {
+                let rows = sqlx::query_as::<_, BucketConfigRow>(
+                    "SELECT name, initial, capacity, refill_amount, refill_interval_ms, algorithm
+                        FROM rate_limiter_buckets",
+                )
+                .fetch_all(pool)
+                .await?;
+                let mut buckets = rows
+                    .iter()
+                    .map(|row| {
+                        let bucket = PgRateLimiter::new(pool.clone(), &row.name, row.try_into()?);
+                        Ok((row.name.clone(), Bucket::Postgres(bucket)))
+                    })
+                    .collect::<Result<Vec<_>, AppError>>()?;
+                if !buckets.iter().any(|(name, _)| name == BUCKET_NAME) {
+                    buckets.push((BUCKET_NAME.to_string(), self.get(BUCKET_NAME).await?));
+                }
+                buckets
+            }
+        };
+        buckets.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(buckets)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Outcome {
+    Withdrawn,
+    Rejected,
+    Invalid,
+}
+
+impl Outcome {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Outcome::Withdrawn => "withdrawn",
+            Outcome::Rejected => "rejected",
+            Outcome::Invalid => "invalid",
+        }
+    }
+}
+
+/// Identify the caller for the ledger: an explicit `X-Client-Key` header wins,
+/// then the first hop of `X-Forwarded-For`.
+fn client_key(headers: &HeaderMap) -> String {
+    let forwarded_for = headers
+        .get("x-forwarded-for")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(',').next());
+    headers
+        .get("x-client-key")
+        .and_then(|value| value.to_str().ok())
+        .or(forwarded_for)
+        .map(str::trim)
+        .filter(|key| !key.is_empty())
+        .unwrap_or("anonymous")
+        .to_string()
+}
+
+/// Parse durations such as `500ms`, `30s`, `5m`, `1h` or `7d`. A bare number
+/// is read as seconds.
+fn parse_duration(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(value.len());
+    let (amount, unit) = value.split_at(split);
+    let amount: f64 = amount.parse().ok()?;
+    let seconds = match unit.trim() {
+        "ms" => amount / 1000.0,
+        "" | "s" => amount,
+        "m" => amount * 60.0,
+        "h" => amount * 60.0 * 60.0,
+        "d" => amount * 60.0 * 60.0 * 24.0,
+        _ => return None,
+    };
+    Duration::try_from_secs_f64(seconds).ok()
+}
+
+/// The unit of one withdrawal, if it had one, and what became of it.
+type LedgerEntry = (Option<&'static str>, Outcome);
+
+/// Append-only record of every withdrawal request, kept in Postgres so all
+/// instances report the same statistics.
+#[derive(Debug, Clone)]
+struct Ledger {
+    pool: PgPool,
+}
+
+impl Ledger {
+    /// Failing to write the ledger must not cost the caller their milk, or
+    /// hold up their response, so the entries of a request are written
+    /// together in the background and errors are logged rather than returned.
+    fn record(&self, resource: &str, client: &str, entries: Vec<LedgerEntry>) {
+        let (units, outcomes): (Vec<_>, Vec<_>) = entries
+            .into_iter()
+            .map(|(unit, outcome)| (unit, outcome.as_str()))
+            .unzip();
+        let query = sqlx::query(
+            "INSERT INTO milk_ledger (resource, client, unit, outcome)
+                SELECT $1, $2, * FROM UNNEST($3::text[], $4::text[])",
+        )
+        .bind(resource.to_string())
+        .bind(client.to_string())
+        .bind(units)
+        .bind(outcomes);
+        let pool = self.pool.clone();
+        tokio::spawn(async move {
+            if let Err(e) = query.execute(&pool).await {
+                eprintln!("Failed to record milk ledger entries: {:?}", e);
+            }
+        });
+    }
+}
+
+#[derive(Deserialize)]
+struct StatsParams {
+    window: Option<String>,
+    resource: Option<String>,
+}
+
+#[derive(FromRow, Serialize, Debug)]
+struct ClientStats {
+    client: String,
+    total: i64,
+    withdrawn: i64,
+    rejected: i64,
+    invalid: i64,
+}
+
+#[derive(Serialize, Debug)]
+struct MilkStats {
+    since: Option<DateTime<Utc>>,
+    resource: Option<String>,
+    total: i64,
+    withdrawn: i64,
+    rejected: i64,
+    invalid: i64,
+    rejection_rate: f64,
+    units: HashMap<String, i64>,
+    clients: Vec<ClientStats>,
+}
+
+async fn milk_stats(
+    State(state): State<Arc<AppState>>,
+    Query(params): Query<StatsParams>,
+) -> Result<Json<MilkStats>, AppError> {
+    let since = match params.window {
+        Some(window) => {
+            let duration = parse_duration(&window)
+                .and_then(|duration| chrono::Duration::from_std(duration).ok())
+                .ok_or(AppError::InvalidWindow(window))?;
+            Some(Utc::now() - duration)
+        }
+        None => None,
+    };
+
+    let clients = sqlx::query_as::<_, ClientStats>(
+        "
+            SELECT client,
+                COUNT(*) AS total,
+                COUNT(*) FILTER (WHERE outcome = 'withdrawn') AS withdrawn,
+                COUNT(*) FILTER (WHERE outcome = 'rejected') AS rejected,
+                COUNT(*) FILTER (WHERE outcome = 'invalid') AS invalid
+            FROM milk_ledger
+            WHERE ($1::timestamptz IS NULL OR created_at >= $1)
+                AND ($2::text IS NULL OR resource = $2)
+            GROUP BY client
+            ORDER BY total DESC, client ASC
+            ",
+    )
+    .bind(since)
+    .bind(&params.resource)
+    .fetch_all(&state.ledger.pool)
+    .await?;
+    let units: Vec<(String, i64)> = sqlx::query_as(
+        "
+            SELECT unit, COUNT(*) FROM milk_ledger
+            WHERE unit IS NOT NULL
+                AND ($1::timestamptz IS NULL OR created_at >= $1)
+                AND ($2::text IS NULL OR resource = $2)
+            GROUP BY unit
+            ",
+    )
+    .bind(since)
+    .bind(&params.resource)
+    .fetch_all(&state.ledger.pool)
+    .await?;
+
+    let total = clients.iter().map(|c| c.total).sum();
+    let rejected = clients.iter().map(|c| c.rejected).sum();
+    Ok(Json(MilkStats {
+        since,
+        resource: params.resource,
+        total,
+        withdrawn: clients.iter().map(|c| c.withdrawn).sum(),
+        rejected,
+        invalid: clients.iter().map(|c| c.invalid).sum(),
+        rejection_rate: if total == 0 {
+            0.0
+        } else {
+            rejected as f64 / total as f64
+        },
+        units: units.into_iter().collect(),
+        clients,
+    }))
+}
+
+async fn refill_bucket(state: State<Arc<AppState>>) -> Result<String, AppError> {
+    refill_resource(state, Path(BUCKET_NAME.to_string())).await
+}
+
+async fn refill_resource(
+    State(state): State<Arc<AppState>>,
+    Path(resource): Path<String>,
+) -> Result<String, AppError> {
+    state.buckets.get(&resource).await?.refill().await?;
     Ok("Bucket refilled\n".to_string())
 }
 
-pub fn router() -> Router {
-    let rate_limiter = Arc::new(Mutex::new(get_rate_limiter()));
+#[derive(Deserialize, Debug)]
+struct NewBucket {
+    name: String,
+    capacity: usize,
+    initial: Option<usize>,
+    refill: Option<usize>,
+    interval_ms: Option<u64>,
+    algorithm: Option<Algorithm>,
+}
+
+#[derive(Serialize, Debug)]
+struct BucketLevel {
+    name: String,
+    algorithm: Algorithm,
+    tokens: usize,
+    capacity: usize,
+    initial: usize,
+    refill: usize,
+    interval_ms: u128,
+    next_refill_ms: Option<u128>,
+    last_manual_refill: Option<DateTime<Utc>>,
+}
+
+impl BucketLevel {
+    async fn of(name: String, bucket: &Bucket) -> Result<Self, AppError> {
+        let config = bucket.config();
+        let snapshot = bucket.snapshot().await?;
+        Ok(BucketLevel {
+            name,
+            algorithm: config.algorithm,
+            tokens: snapshot.tokens,
+            capacity: config.capacity,
+            initial: config.initial,
+            refill: config.refill,
+            interval_ms: config.interval.as_millis(),
+            next_refill_ms: snapshot.next_refill.map(|next| next.as_millis()),
+            last_manual_refill: snapshot.manually_refilled_at,
+        })
+    }
+}
+
+async fn create_bucket(
+    State(state): State<Arc<AppState>>,
+    Json(new_bucket): Json<NewBucket>,
+) -> Result<(StatusCode, Json<BucketLevel>), AppError> {
+    let name = new_bucket.name;
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '-' || c == '_')
+    {
+        return Err(AppError::InvalidBucket(format!(
+            "name must be lowercase letters, digits, '-' or '_': {}",
+            name
+        )));
+    }
+    let config = BucketConfig {
+        initial: new_bucket.initial.unwrap_or(new_bucket.capacity),
+        capacity: new_bucket.capacity,
+        refill: new_bucket.refill.unwrap_or(1),
+        interval: Duration::from_millis(new_bucket.interval_ms.unwrap_or(1000)),
+        algorithm: new_bucket.algorithm.unwrap_or_default(),
+    };
+    config.validate()?;
+    let bucket = state.buckets.create(&name, config).await?;
+    Ok((
+        StatusCode::CREATED,
+        Json(BucketLevel::of(name, &bucket).await?),
+    ))
+}
+
+async fn inspect_bucket(state: State<Arc<AppState>>) -> Result<Json<BucketLevel>, AppError> {
+    inspect_resource(state, Path(BUCKET_NAME.to_string())).await
+}
+
+async fn inspect_resource(
+    State(state): State<Arc<AppState>>,
+    Path(resource): Path<String>,
+) -> Result<Json<BucketLevel>, AppError> {
+    let bucket = state.buckets.get(&resource).await?;
+    Ok(Json(BucketLevel::of(resource, &bucket).await?))
+}
+
+async fn list_buckets(
+    State(state): State<Arc<AppState>>,
+) -> Result<Json<Vec<BucketLevel>>, AppError> {
+    let mut levels = Vec::new();
+    for (name, bucket) in state.buckets.list().await? {
+        levels.push(BucketLevel::of(name, &bucket).await?);
+    }
+    Ok(Json(levels))
+}
+
+pub fn router(pool: PgPool, secrets: &SecretStore) -> Router {
+    let state = Arc::new(AppState {
+        buckets: BucketRegistry::from_secrets(pool.clone(), secrets),
+        ledger: Ledger { pool },
+    });
     Router::new()
         .route("/milk", post(get_milk))
         .route("/refill", post(refill_bucket))
-        .with_state(rate_limiter.clone())
+        .route("/stats", get(milk_stats))
+        .route("/bucket", get(inspect_bucket))
+        .route("/buckets", get(list_buckets).post(create_bucket))
+        .route("/:resource/withdraw", post(withdraw))
+        .route("/:resource/refill", post(refill_resource))
+        .route("/:resource/bucket", get(inspect_resource))
+        .with_state(state)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    const SLOW_BUCKET: BucketConfig = BucketConfig {
+        initial: 5,
+        capacity: 5,
+        refill: 1,
+        interval: Duration::from_secs(3600),
+        algorithm: Algorithm::LeakyBucket,
+    };
+
+    #[test]
+    fn batches_report_each_item() {
+        let conversions = Conversions::from_value(json!([
+            {"liters": 1.0},
+            {"furlongs": 2.0},
+            {"pints": "three"},
+        ]))
+        .unwrap()
+        .convert(&NumberFormat::default())
+        .unwrap();
+        assert_eq!(conversions.units, vec![Some("liters"), None, None]);
+        let results = conversions.body;
+        assert_eq!(results[0], json!({"gallons": 0.264172}));
+        assert!(results[1]["error"].is_string());
+        assert!(results[2]["error"].is_string());
+    }
+
+    #[test]
+    fn pairs_convert_like_objects() {
+        let format = NumberFormat::default();
+        let single = Conversions::from_pairs(vec![("gallons".into(), "2".into())])
+            .unwrap()
+            .convert(&format)
+            .unwrap();
+        assert_eq!(single.body, json!({"liters": 2.0 * 3.78541}));
+
+        let batch = Conversions::from_pairs(vec![
+            ("litres".into(), "1".into()),
+            ("pints".into(), "nan".into()),
+        ])
+        .unwrap()
+        .convert(&format)
+        .unwrap();
+        assert_eq!(batch.units, vec![Some("litres"), None]);
+
+        assert!(Conversions::from_pairs(vec![("liters".into(), "x".into())]).is_err());
+    }
+
+    #[tokio::test]
+    async fn only_units_in_the_query_are_conversions() {
+        let conversions = |uri: &str| {
+            let req = Request::builder()
+                .uri(uri)
+                .body(axum::body::Body::empty())
+                .unwrap();
+            async { read_conversions(&HeaderMap::new(), req).await }
+        };
+        assert!(conversions("/9/milk?foo=bar&wait=1s").await.is_none());
+        assert!(matches!(
+            conversions("/9/milk?liters=2&foo=bar").await,
+            Some(Ok(Conversions::Single(Unit::Liters(_))))
+        ));
+        assert!(conversions("/9/milk?pints=lots").await.unwrap().is_err());
+    }
+
+    #[test]
+    fn durations_take_a_unit() {
+        assert_eq!(parse_duration("500ms"), Some(Duration::from_millis(500)));
+        assert_eq!(parse_duration("30"), Some(Duration::from_secs(30)));
+        assert_eq!(parse_duration(" 1.5s "), Some(Duration::from_millis(1500)));
+        assert_eq!(parse_duration("5m"), Some(Duration::from_secs(300)));
+        assert_eq!(parse_duration("1h"), Some(Duration::from_secs(3600)));
+        assert_eq!(parse_duration("7d"), Some(Duration::from_secs(604_800)));
+        for invalid in ["", "s", "-1s", "1w", "1.2.3s", "soon"] {
+            assert_eq!(parse_duration(invalid), None, "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn clients_are_told_apart_by_key_then_address() {
+        let headers = |pairs: &[(&'static str, &'static str)]| {
+            let mut headers = HeaderMap::new();
+            for (name, value) in pairs {
+                headers.insert(*name, HeaderValue::from_static(value));
+            }
+            headers
+        };
+        assert_eq!(client_key(&headers(&[])), "anonymous");
+        assert_eq!(
+            client_key(&headers(&[("x-forwarded-for", " 10.0.0.1 , 10.0.0.2")])),
+            "10.0.0.1"
+        );
+        assert_eq!(
+            client_key(&headers(&[
+                ("x-client-key", "rudolph"),
+                ("x-forwarded-for", "10.0.0.1"),
+            ])),
+            "rudolph"
+        );
+        assert_eq!(client_key(&headers(&[("x-client-key", "  ")])), "anonymous");
+    }
+
+    #[test]
+    fn waits_come_from_the_query_then_prefer() {
+        let params = |wait: Option<&str>| MilkParams {
+            wait: wait.map(str::to_string),
+            precision: None,
+            significant_figures: None,
+            rounding: None,
+            exact: false,
+        };
+        let prefer = |value: &'static str| {
+            let mut headers = HeaderMap::new();
+            headers.insert("prefer", HeaderValue::from_static(value));
+            headers
+        };
+        let wait = |params: &MilkParams, headers: &HeaderMap| {
+            requested_wait(params, headers).map_err(|e| e.to_string())
+        };
+
+        assert_eq!(wait(&params(None), &HeaderMap::new()), Ok(None));
+        assert_eq!(
+            wait(&params(Some("2s")), &prefer("wait=10")),
+            Ok(Some(Duration::from_secs(2)))
+        );
+        assert_eq!(
+            wait(&params(None), &prefer("respond-async; Wait=\"500ms\"")),
+            Ok(Some(Duration::from_millis(500)))
+        );
+        assert_eq!(wait(&params(None), &prefer("handling=lenient")), Ok(None));
+        assert_eq!(
+            wait(&params(Some("1h")), &HeaderMap::new()),
+            Ok(Some(MAX_WAIT))
+        );
+        assert!(wait(&params(Some("soon")), &HeaderMap::new()).is_err());
+    }
+
+    #[test]
+    fn bucket_configs_are_bounded() {
+        assert!(SLOW_BUCKET.validate().is_ok());
+        for config in [
+            BucketConfig {
+                capacity: MAX_BUCKET_TOKENS + 1,
+                ..SLOW_BUCKET
+            },
+            BucketConfig {
+                refill: u32::MAX as usize + 2,
+                ..SLOW_BUCKET
+            },
+            BucketConfig {
+                interval: MAX_BUCKET_INTERVAL + Duration::from_millis(1),
+                ..SLOW_BUCKET
+            },
+        ] {
+            assert!(config.validate().is_err(), "{:?}", config);
+        }
+
+        let row = BucketConfigRow {
+            name: BUCKET_NAME.to_string(),
+            initial: -1,
+            capacity: 5,
+            refill_amount: 1,
+            refill_interval_ms: 1000,
+            algorithm: "gcra".to_string(),
+        };
+        assert!(BucketConfig::try_from(&row).is_err());
+        let row = BucketConfigRow { initial: 5, ..row };
+        assert!(BucketConfig::try_from(&row).is_ok());
+    }
+
+    #[tokio::test]
+    async fn in_memory_leaky_buckets_use_leaky_bucket() {
+        let bucket = Bucket::in_memory(SLOW_BUCKET);
+        let Bucket::InMemory { limiter, .. } = &bucket else {
+            unreachable!()
+        };
+        assert!(matches!(**limiter.read().await, Limiter::LeakyBucket(_)));
+        for _ in 0..5 {
+            assert!(bucket.try_acquire(1).await.unwrap());
+        }
+        assert!(!bucket.try_acquire(1).await.unwrap());
+        assert!(!bucket
+            .acquire_within(1, Duration::from_millis(10))
+            .await
+            .unwrap());
+
+        let snapshot = bucket.snapshot().await.unwrap();
+        assert_eq!(snapshot.tokens, 0);
+        assert!(snapshot.next_refill.unwrap() <= SLOW_BUCKET.interval);
+        bucket.refill().await.unwrap();
+        let snapshot = bucket.snapshot().await.unwrap();
+        assert_eq!((snapshot.tokens, snapshot.next_refill), (5, None));
+    }
+
+    #[tokio::test]
+    async fn leaky_bucket_snapshots_take_nothing() {
+        let config = BucketConfig {
+            initial: 3,
+            interval: Duration::from_millis(50),
+            ..SLOW_BUCKET
+        };
+        let limiter = Arc::new(LeakyBucketLimiter::new(&config, None));
+        for _ in 0..3 {
+            assert_eq!(limiter.snapshot().tokens, 3);
+        }
+        assert!(limiter.limiter.try_acquire(3));
+        let waiter = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.limiter.acquire(2).await }
+        });
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        for _ in 0..3 {
+            assert_eq!(limiter.snapshot().tokens, 0);
+        }
+        tokio::time::timeout(Duration::from_secs(1), waiter)
+            .await
+            .unwrap()
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn only_waiting_callers_hold_up_the_rest() {
+        let config = BucketConfig {
+            algorithm: Algorithm::FixedWindow,
+            capacity: 100,
+            initial: 100,
+            ..SLOW_BUCKET
+        };
+        let limiter = Arc::new(PolicyLimiter::new(&config, None));
+        let threads: Vec<_> = (0..8)
+            .map(|_| {
+                let limiter = limiter.clone();
+                std::thread::spawn(move || (0..20).filter(|_| limiter.try_acquire(1)).count())
+            })
+            .collect();
+        let granted: usize = threads.into_iter().map(|t| t.join().unwrap()).sum();
+        assert_eq!(granted, 100);
+
+        let waiter = tokio::spawn({
+            let limiter = limiter.clone();
+            async move { limiter.acquire(1).await }
+        });
+        while limiter.waiting.load(Ordering::SeqCst) == 0 {
+            tokio::task::yield_now().await;
+        }
+        assert!(!limiter.try_acquire(0));
+        waiter.abort();
+        let _ = waiter.await;
+        assert!(limiter.try_acquire(0));
+    }
+
+    async fn acquire_concurrently(limiters: Vec<PgRateLimiter>, attempts: usize) -> usize {
+        let handles: Vec<_> = (0..attempts)
+            .map(|i| {
+                let limiter = limiters[i % limiters.len()].clone();
+                tokio::spawn(async move { limiter.try_acquire(1).await.unwrap() })
+            })
+            .collect();
+        let mut granted = 0;
+        for handle in handles {
+            if handle.await.unwrap() {
+                granted += 1;
+            }
+        }
+        granted
+    }
+
+    #[sqlx::test]
+    async fn concurrent_acquisitions_never_overdraw(pool: PgPool) {
+        let limiter = PgRateLimiter::new(pool, BUCKET_NAME, SLOW_BUCKET);
+        assert_eq!(acquire_concurrently(vec![limiter], 100).await, 5);
+    }
+
+    #[sqlx::test]
+    async fn instances_share_one_bucket(pool: PgPool) {
+        let instances = (0..4)
+            .map(|_| PgRateLimiter::new(pool.clone(), BUCKET_NAME, SLOW_BUCKET))
+            .collect();
+        assert_eq!(acquire_concurrently(instances, 100).await, 5);
+    }
+
+    #[sqlx::test]
+    async fn every_algorithm_is_shared_across_instances(pool: PgPool) {
+        let algorithms = [
+            Algorithm::LeakyBucket,
+            Algorithm::FixedWindow,
+            Algorithm::SlidingWindowLog,
+            Algorithm::SlidingWindowCounter,
+            Algorithm::Gcra,
+        ];
+        for algorithm in algorithms {
+            let config = BucketConfig {
+                algorithm,
+                ..SLOW_BUCKET
+            };
+            let instances = (0..4)
+                .map(|_| PgRateLimiter::new(pool.clone(), algorithm.as_str(), config))
+                .collect();
+            assert_eq!(
+                acquire_concurrently(instances, 50).await,
+                5,
+                "{algorithm} let too much through"
+            );
+        }
+    }
+
+    #[sqlx::test]
+    async fn buckets_are_isolated_by_name(pool: PgPool) {
+        let milk = PgRateLimiter::new(pool.clone(), BUCKET_NAME, SLOW_BUCKET);
+        let cocoa = PgRateLimiter::new(pool, "cocoa", SLOW_BUCKET);
+        assert_eq!(acquire_concurrently(vec![milk], 10).await, 5);
+        assert!(cocoa.try_acquire(1).await.unwrap());
+    }
+
+    #[sqlx::test]
+    async fn refill_restores_initial_tokens(pool: PgPool) {
+        let limiter = PgRateLimiter::new(pool.clone(), BUCKET_NAME, SLOW_BUCKET);
+        assert!(limiter.try_acquire(5).await.unwrap());
+        assert!(!limiter.try_acquire(1).await.unwrap());
+        sqlx::query("UPDATE rate_limiter_buckets SET tokens = 0")
+            .execute(&pool)
+            .await
+            .unwrap();
+        limiter.refill().await.unwrap();
+        // Instances of the previous release still read `tokens`.
+        let tokens: i64 = sqlx::query_scalar("SELECT tokens FROM rate_limiter_buckets")
+            .fetch_one(&pool)
+            .await
+            .unwrap();
+        assert_eq!(tokens, 5);
+        assert_eq!(acquire_concurrently(vec![limiter], 20).await, 5);
+    }
+
+    #[sqlx::test]
+    async fn leaky_buckets_are_shared_with_the_previous_release(pool: PgPool) {
+        let limiter = PgRateLimiter::new(pool.clone(), BUCKET_NAME, SLOW_BUCKET);
+        assert!(limiter.try_acquire(2).await.unwrap());
+        let tokens = || {
+            sqlx::query_scalar::<_, i64>("SELECT tokens FROM rate_limiter_buckets").fetch_one(&pool)
+        };
+        assert_eq!(tokens().await.unwrap(), 3);
+        // An instance of the previous release takes two more.
+        sqlx::query("UPDATE rate_limiter_buckets SET tokens = tokens - 2")
+            .execute(&pool)
+            .await
+            .unwrap();
+        assert_eq!(acquire_concurrently(vec![limiter], 20).await, 1);
+        assert_eq!(tokens().await.unwrap(), 0);
+    }
+
+    #[sqlx::test]
+    async fn tokens_leak_back_over_time(pool: PgPool) {
+        let config = BucketConfig {
+            initial: 1,
+            capacity: 2,
+            refill: 1,
+            interval: Duration::from_millis(200),
+            algorithm: Algorithm::LeakyBucket,
+        };
+        let limiter = PgRateLimiter::new(pool, BUCKET_NAME, config);
+        assert!(limiter.try_acquire(1).await.unwrap());
+        assert!(!limiter.try_acquire(1).await.unwrap());
+        tokio::time::sleep(Duration::from_millis(1000)).await;
+        assert!(limiter.try_acquire(1).await.unwrap());
+        assert!(limiter.try_acquire(1).await.unwrap());
+        assert!(!limiter.try_acquire(1).await.unwrap());
+    }
 }
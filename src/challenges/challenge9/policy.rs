@@ -0,0 +1,702 @@
+use serde::{Deserialize, Serialize};
+use std::{collections::VecDeque, fmt, str::FromStr, time::Duration};
+
+use super::BucketConfig;
+
+/// A rate-limiting algorithm.
+///
+/// Policies never read the clock themselves: every call is given `now`, the
+/// time elapsed since a fixed origin (the UNIX epoch in production). That keeps
+/// them deterministic and lets the state be stored in Postgres and replayed on
+/// any instance.
+pub(super) trait RateLimitPolicy {
+    /// Take `permits` at `now` if the policy admits them.
+    fn try_acquire(&mut self, permits: usize, now: Duration) -> bool;
+
+    /// Permits that would be admitted at `now`, without taking any.
+    fn available(&self, now: Duration) -> usize;
+
+    /// How long after `now` until `permits` could be admitted, or `None` if
+    /// they never can be.
+    fn retry_after(&self, permits: usize, now: Duration) -> Option<Duration>;
+
+    /// How long after `now` until one more permit becomes available, or `None`
+    /// if the policy is already at capacity.
+    fn next_refill(&self, now: Duration) -> Option<Duration> {
+        self.retry_after(self.available(now) + 1, now)
+    }
+}
+
+/// Which [`RateLimitPolicy`] a bucket uses. Every algorithm admits at most
+/// `capacity` permits in a burst; how they come back differs:
+///
+/// - `leaky_bucket` starts with `initial` tokens and adds `refill` tokens every
+///   `interval`.
+/// - `fixed_window` admits `capacity` per `interval`, with windows aligned to
+///   multiples of `interval`.
+/// - `sliding_window_log` admits `capacity` in any trailing `interval`.
+/// - `sliding_window_counter` approximates the log by weighting the previous
+///   fixed window by how much of it still overlaps the trailing `interval`.
+/// - `gcra` spaces permits `interval / refill` apart, allowing bursts of up to
+///   `capacity`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Algorithm {
+    #[default]
+    LeakyBucket,
+    FixedWindow,
+    SlidingWindowLog,
+    SlidingWindowCounter,
+    Gcra,
+}
+
+impl Algorithm {
+    pub(super) fn as_str(&self) -> &'static str {
+        match self {
+            Algorithm::LeakyBucket => "leaky_bucket",
+            Algorithm::FixedWindow => "fixed_window",
+            Algorithm::SlidingWindowLog => "sliding_window_log",
+            Algorithm::SlidingWindowCounter => "sliding_window_counter",
+            Algorithm::Gcra => "gcra",
+        }
+    }
+}
+
+impl fmt::Display for Algorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.as_str())
+    }
+}
+
+impl FromStr for Algorithm {
+    type Err = String;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "leaky_bucket" => Ok(Algorithm::LeakyBucket),
+            "fixed_window" => Ok(Algorithm::FixedWindow),
+            "sliding_window_log" => Ok(Algorithm::SlidingWindowLog),
+            "sliding_window_counter" => Ok(Algorithm::SlidingWindowCounter),
+            "gcra" => Ok(Algorithm::Gcra),
+            _ => Err(format!("Unknown rate limiting algorithm: {}", s)),
+        }
+    }
+}
+
+/// The state of whichever algorithm a bucket uses.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "algorithm", rename_all = "snake_case")]
+pub(super) enum Policy {
+    LeakyBucket(LeakyBucket),
+    FixedWindow(FixedWindow),
+    SlidingWindowLog(SlidingWindowLog),
+    SlidingWindowCounter(SlidingWindowCounter),
+    Gcra(Gcra),
+}
+
+impl Policy {
+    pub(super) fn new(config: &BucketConfig, now: Duration) -> Self {
+        match config.algorithm {
+            Algorithm::LeakyBucket => Policy::LeakyBucket(LeakyBucket::new(config, now)),
+            Algorithm::FixedWindow => Policy::FixedWindow(FixedWindow::new(config)),
+            Algorithm::SlidingWindowLog => Policy::SlidingWindowLog(SlidingWindowLog::new(config)),
+            Algorithm::SlidingWindowCounter => {
+                Policy::SlidingWindowCounter(SlidingWindowCounter::new(config))
+            }
+            Algorithm::Gcra => Policy::Gcra(Gcra::new(config, now)),
+        }
+    }
+
+    fn inner(&self) -> &dyn RateLimitPolicy {
+        match self {
+            Policy::LeakyBucket(policy) => policy,
+            Policy::FixedWindow(policy) => policy,
+            Policy::SlidingWindowLog(policy) => policy,
+            Policy::SlidingWindowCounter(policy) => policy,
+            Policy::Gcra(policy) => policy,
+        }
+    }
+
+    fn inner_mut(&mut self) -> &mut dyn RateLimitPolicy {
+        match self {
+            Policy::LeakyBucket(policy) => policy,
+            Policy::FixedWindow(policy) => policy,
+            Policy::SlidingWindowLog(policy) => policy,
+            Policy::SlidingWindowCounter(policy) => policy,
+            Policy::Gcra(policy) => policy,
+        }
+    }
+}
+
+impl RateLimitPolicy for Policy {
+    fn try_acquire(&mut self, permits: usize, now: Duration) -> bool {
+        self.inner_mut().try_acquire(permits, now)
+    }
+
+    fn available(&self, now: Duration) -> usize {
+        self.inner().available(now)
+    }
+
+    fn retry_after(&self, permits: usize, now: Duration) -> Option<Duration> {
+        self.inner().retry_after(permits, now)
+    }
+}
+
+/// `interval * n`, saturating instead of overflowing.
+fn times(interval: Duration, n: u128) -> Duration {
+    Duration::from_nanos((interval.as_nanos() * n).min(u64::MAX as u128) as u64)
+}
+
+/// Start of the `window`-aligned window containing `now`.
+fn window_start(now: Duration, window: Duration) -> Duration {
+    now - Duration::from_nanos((now.as_nanos() % window.as_nanos()) as u64)
+}
+
+/// Same semantics as the `leaky_bucket` crate: `refill` tokens are added every
+/// `interval`, counted from when the bucket was created, up to `capacity`.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct LeakyBucket {
+    capacity: usize,
+    refill: usize,
+    interval: Duration,
+    tokens: usize,
+    next_refill: Duration,
+}
+
+impl LeakyBucket {
+    fn new(config: &BucketConfig, now: Duration) -> Self {
+        LeakyBucket {
+            capacity: config.capacity,
+            refill: config.refill,
+            interval: config.interval,
+            tokens: config.initial,
+            next_refill: now + config.interval,
+        }
+    }
+
+    /// The bucket as the previous release keeps it in Postgres: its tokens
+    /// and when it was last refilled.
+    pub(super) fn from_columns(
+        config: &BucketConfig,
+        tokens: usize,
+        refilled_at: Duration,
+    ) -> Self {
+        LeakyBucket {
+            tokens: tokens.min(config.capacity),
+            next_refill: refilled_at + config.interval,
+            ..LeakyBucket::new(config, refilled_at)
+        }
+    }
+
+    /// The tokens and last refill time to keep for the previous release.
+    pub(super) fn columns(&self) -> (usize, Duration) {
+        (self.tokens, self.next_refill.saturating_sub(self.interval))
+    }
+
+    /// Tokens and next refill time once every refill due by `now` is applied.
+    fn refilled(&self, now: Duration) -> (usize, Duration) {
+        if now < self.next_refill {
+            return (self.tokens, self.next_refill);
+        }
+        let intervals = (now - self.next_refill).as_nanos() / self.interval.as_nanos() + 1;
+        let tokens = (self.tokens as u128 + intervals * self.refill as u128)
+            .min(self.capacity as u128) as usize;
+        (tokens, self.next_refill + times(self.interval, intervals))
+    }
+}
+
+impl RateLimitPolicy for LeakyBucket {
+    fn try_acquire(&mut self, permits: usize, now: Duration) -> bool {
+        (self.tokens, self.next_refill) = self.refilled(now);
+        if self.tokens < permits {
+            return false;
+        }
+        self.tokens -= permits;
+        true
+    }
+
+    fn available(&self, now: Duration) -> usize {
+        self.refilled(now).0
+    }
+
+    fn retry_after(&self, permits: usize, now: Duration) -> Option<Duration> {
+        if permits > self.capacity {
+            return None;
+        }
+        let (tokens, next_refill) = self.refilled(now);
+        if tokens >= permits {
+            return Some(Duration::ZERO);
+        }
+        let refills = (permits - tokens).div_ceil(self.refill) as u128;
+        Some(next_refill + times(self.interval, refills - 1) - now)
+    }
+}
+
+/// Admits `limit` permits per window; the count resets at each window boundary.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct FixedWindow {
+    limit: usize,
+    window: Duration,
+    window_start: Duration,
+    count: usize,
+}
+
+impl FixedWindow {
+    fn new(config: &BucketConfig) -> Self {
+        FixedWindow {
+            limit: config.capacity,
+            window: config.interval,
+            window_start: Duration::ZERO,
+            count: 0,
+        }
+    }
+
+    fn current(&self, now: Duration) -> (Duration, usize) {
+        let start = window_start(now, self.window);
+        if start == self.window_start {
+            (start, self.count)
+        } else {
+            (start, 0)
+        }
+    }
+}
+
+impl RateLimitPolicy for FixedWindow {
+    fn try_acquire(&mut self, permits: usize, now: Duration) -> bool {
+        (self.window_start, self.count) = self.current(now);
+        if self.count + permits > self.limit {
+            return false;
+        }
+        self.count += permits;
+        true
+    }
+
+    fn available(&self, now: Duration) -> usize {
+        self.limit.saturating_sub(self.current(now).1)
+    }
+
+    fn retry_after(&self, permits: usize, now: Duration) -> Option<Duration> {
+        if permits > self.limit {
+            return None;
+        }
+        let (start, count) = self.current(now);
+        if count + permits <= self.limit {
+            return Some(Duration::ZERO);
+        }
+        Some(start + self.window - now)
+    }
+}
+
+/// Remembers when every permit was taken and admits `limit` in any trailing
+/// window. Exact, at the cost of storing up to `limit` timestamps.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct SlidingWindowLog {
+    limit: usize,
+    window: Duration,
+    log: VecDeque<Duration>,
+}
+
+impl SlidingWindowLog {
+    fn new(config: &BucketConfig) -> Self {
+        SlidingWindowLog {
+            limit: config.capacity,
+            window: config.interval,
+            log: VecDeque::new(),
+        }
+    }
+
+    /// Index of the first entry still inside the window ending at `now`.
+    fn first_live(&self, now: Duration) -> usize {
+        self.log
+            .partition_point(|taken| *taken + self.window <= now)
+    }
+}
+
+impl RateLimitPolicy for SlidingWindowLog {
+    fn try_acquire(&mut self, permits: usize, now: Duration) -> bool {
+        let expired = self.first_live(now);
+        self.log.drain(..expired);
+        if self.log.len() + permits > self.limit {
+            return false;
+        }
+        self.log.extend(std::iter::repeat_n(now, permits));
+        true
+    }
+
+    fn available(&self, now: Duration) -> usize {
+        let live = self.log.len() - self.first_live(now);
+        self.limit.saturating_sub(live)
+    }
+
+    fn retry_after(&self, permits: usize, now: Duration) -> Option<Duration> {
+        if permits > self.limit {
+            return None;
+        }
+        let first_live = self.first_live(now);
+        let live = self.log.len() - first_live;
+        if live + permits <= self.limit {
+            return Some(Duration::ZERO);
+        }
+        // The oldest entries have to age out until there is room.
+        let blocking = self.log[first_live + live + permits - self.limit - 1];
+        Some(blocking + self.window - now)
+    }
+}
+
+/// Counts permits per aligned window, and estimates the trailing window by
+/// weighting the previous window's count by how much of it still overlaps.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct SlidingWindowCounter {
+    limit: usize,
+    window: Duration,
+    window_start: Duration,
+    current: usize,
+    previous: usize,
+}
+
+impl SlidingWindowCounter {
+    fn new(config: &BucketConfig) -> Self {
+        SlidingWindowCounter {
+            limit: config.capacity,
+            window: config.interval,
+            window_start: Duration::ZERO,
+            current: 0,
+            previous: 0,
+        }
+    }
+
+    /// Window start, current count and previous count as of `now`.
+    fn rolled(&self, now: Duration) -> (Duration, usize, usize) {
+        let start = window_start(now, self.window);
+        if start == self.window_start {
+            (start, self.current, self.previous)
+        } else if start == self.window_start + self.window {
+            (start, 0, self.current)
+        } else {
+            (start, 0, 0)
+        }
+    }
+
+    /// Nanoseconds of the previous window that still overlap the trailing
+    /// window ending at `now`.
+    fn overlap(&self, start: Duration, now: Duration) -> u128 {
+        self.window.as_nanos() - (now - start).as_nanos()
+    }
+}
+
+impl RateLimitPolicy for SlidingWindowCounter {
+    fn try_acquire(&mut self, permits: usize, now: Duration) -> bool {
+        (self.window_start, self.current, self.previous) = self.rolled(now);
+        if permits > self.available(now) {
+            return false;
+        }
+        self.current += permits;
+        true
+    }
+
+    fn available(&self, now: Duration) -> usize {
+        // Integer form of `previous * overlap / window + current + permits <= limit`.
+        let (start, current, previous) = self.rolled(now);
+        let window = self.window.as_nanos();
+        let weighted = previous as u128 * self.overlap(start, now);
+        let budget = (self.limit as u128 * window).saturating_sub(weighted) / window;
+        (budget as usize).saturating_sub(current)
+    }
+
+    fn retry_after(&self, permits: usize, now: Duration) -> Option<Duration> {
+        if permits > self.limit {
+            return None;
+        }
+        if permits <= self.available(now) {
+            return Some(Duration::ZERO);
+        }
+        let (mut start, mut current, mut previous) = self.rolled(now);
+        if current + permits > self.limit {
+            // Nothing fits until this window has become the previous one.
+            (start, current, previous) = (start + self.window, 0, current);
+        }
+        // Then wait until the previous window's weight has decayed enough.
+        let window = self.window.as_nanos();
+        let max_overlap = match previous {
+            0 => window,
+            previous => (self.limit - current - permits) as u128 * window / previous as u128,
+        };
+        let ready_at = start + Duration::from_nanos((window - max_overlap.min(window)) as u64);
+        Some(ready_at.saturating_sub(now))
+    }
+}
+
+/// Generic cell rate algorithm: tracks the theoretical arrival time (TAT) of
+/// the next permit, one emission interval apart, and admits a request if it
+/// is no more than `burst` emission intervals ahead of now.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct Gcra {
+    emission_interval: Duration,
+    burst: usize,
+    tat: Duration,
+}
+
+impl Gcra {
+    fn new(config: &BucketConfig, now: Duration) -> Self {
+        Gcra {
+            // Refills beyond `u32::MAX` emit no slower than `u32::MAX` would.
+            emission_interval: config.interval
+                / u32::try_from(config.refill.max(1)).unwrap_or(u32::MAX),
+            burst: config.capacity,
+            tat: now,
+        }
+    }
+
+    fn tolerance(&self) -> Duration {
+        times(self.emission_interval, self.burst as u128)
+    }
+
+    fn next_tat(&self, permits: usize, now: Duration) -> Duration {
+        self.tat.max(now) + times(self.emission_interval, permits as u128)
+    }
+}
+
+impl RateLimitPolicy for Gcra {
+    fn try_acquire(&mut self, permits: usize, now: Duration) -> bool {
+        let tat = self.next_tat(permits, now);
+        if tat > now + self.tolerance() {
+            return false;
+        }
+        self.tat = tat;
+        true
+    }
+
+    fn available(&self, now: Duration) -> usize {
+        let backlog = self.tat.saturating_sub(now);
+        let headroom = self.tolerance().saturating_sub(backlog);
+        (headroom.as_nanos() / self.emission_interval.as_nanos().max(1)) as usize
+    }
+
+    fn retry_after(&self, permits: usize, now: Duration) -> Option<Duration> {
+        if permits > self.burst {
+            return None;
+        }
+        let allowed_at = self.next_tat(permits, now).saturating_sub(self.tolerance());
+        Some(allowed_at.saturating_sub(now))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A clock the tests move by hand.
+    struct ManualClock {
+        now: Duration,
+    }
+
+    impl ManualClock {
+        fn new() -> Self {
+            ManualClock {
+                now: Duration::ZERO,
+            }
+        }
+
+        fn advance_to(&mut self, millis: u64) {
+            self.now = Duration::from_millis(millis);
+        }
+    }
+
+    fn config(algorithm: Algorithm) -> BucketConfig {
+        BucketConfig {
+            initial: 3,
+            capacity: 3,
+            refill: 1,
+            interval: Duration::from_millis(1000),
+            algorithm,
+        }
+    }
+
+    /// Run `(time in ms, admitted?)` steps against a fresh policy.
+    fn assert_sequence(algorithm: Algorithm, steps: &[(u64, bool)]) {
+        let mut clock = ManualClock::new();
+        let mut policy = Policy::new(&config(algorithm), clock.now);
+        for (i, &(at, expected)) in steps.iter().enumerate() {
+            clock.advance_to(at);
+            let available = policy.available(clock.now);
+            let retry_after = policy.retry_after(1, clock.now);
+            assert_eq!(
+                policy.try_acquire(1, clock.now),
+                expected,
+                "{algorithm} step {i} at {at}ms"
+            );
+            assert_eq!(available > 0, expected, "{algorithm} available at {at}ms");
+            assert_eq!(
+                retry_after == Some(Duration::ZERO),
+                expected,
+                "{algorithm} retry_after at {at}ms"
+            );
+        }
+    }
+
+    #[test]
+    fn leaky_bucket_sequence() {
+        assert_sequence(
+            Algorithm::LeakyBucket,
+            &[
+                (0, true),
+                (0, true),
+                (0, true),
+                (0, false),
+                (999, false),
+                (1000, true),
+                (1000, false),
+                (3500, true),
+                (3500, true),
+                (3500, false),
+                (4000, true),
+                (4000, false),
+            ],
+        );
+    }
+
+    #[test]
+    fn fixed_window_sequence() {
+        assert_sequence(
+            Algorithm::FixedWindow,
+            &[
+                (0, true),
+                (0, true),
+                (0, true),
+                (0, false),
+                (999, false),
+                (1000, true),
+                (1000, true),
+                (1999, true),
+                (1999, false),
+                (2000, true),
+            ],
+        );
+    }
+
+    #[test]
+    fn sliding_window_log_sequence() {
+        assert_sequence(
+            Algorithm::SlidingWindowLog,
+            &[
+                (0, true),
+                (400, true),
+                (800, true),
+                (900, false),
+                (1000, true),
+                (1000, false),
+                (1399, false),
+                (1400, true),
+                (1400, false),
+            ],
+        );
+    }
+
+    #[test]
+    fn sliding_window_counter_sequence() {
+        assert_sequence(
+            Algorithm::SlidingWindowCounter,
+            &[
+                (0, true),
+                (0, true),
+                (0, true),
+                (0, false),
+                (1000, false),
+                (1500, true),
+                (1500, false),
+                (1700, true),
+                (1700, false),
+                (2000, true),
+                (2000, false),
+                (3500, true),
+                (3500, true),
+                (3500, false),
+            ],
+        );
+    }
+
+    #[test]
+    fn gcra_sequence() {
+        assert_sequence(
+            Algorithm::Gcra,
+            &[
+                (0, true),
+                (0, true),
+                (0, true),
+                (0, false),
+                (999, false),
+                (1000, true),
+                (1000, false),
+                (2500, true),
+                (2500, false),
+                (3000, true),
+            ],
+        );
+    }
+
+    #[test]
+    fn retry_after_points_at_the_next_admission() {
+        let algorithms = [
+            Algorithm::LeakyBucket,
+            Algorithm::FixedWindow,
+            Algorithm::SlidingWindowLog,
+            Algorithm::SlidingWindowCounter,
+            Algorithm::Gcra,
+        ];
+        for algorithm in algorithms {
+            let mut clock = ManualClock::new();
+            clock.advance_to(250);
+            let mut policy = Policy::new(&config(algorithm), clock.now);
+            while policy.try_acquire(1, clock.now) {}
+
+            let wait = policy.retry_after(1, clock.now).unwrap();
+            assert!(wait > Duration::ZERO, "{algorithm} should ask to wait");
+            let just_before = clock.now + wait - Duration::from_millis(1);
+            assert!(
+                !policy.clone().try_acquire(1, just_before),
+                "{algorithm} admitted before retry_after"
+            );
+            assert!(
+                policy.try_acquire(1, clock.now + wait),
+                "{algorithm} rejected at retry_after"
+            );
+        }
+    }
+
+    #[test]
+    fn next_refill_is_none_when_full() {
+        let mut policy = Policy::new(&config(Algorithm::LeakyBucket), Duration::ZERO);
+        assert_eq!(policy.next_refill(Duration::ZERO), None);
+        assert!(policy.try_acquire(2, Duration::from_millis(300)));
+        assert_eq!(
+            policy.next_refill(Duration::from_millis(300)),
+            Some(Duration::from_millis(700))
+        );
+
+        let mut policy = Policy::new(&config(Algorithm::FixedWindow), Duration::ZERO);
+        assert!(policy.try_acquire(1, Duration::from_millis(1200)));
+        assert_eq!(
+            policy.next_refill(Duration::from_millis(1200)),
+            Some(Duration::from_millis(800))
+        );
+    }
+
+    #[test]
+    fn oversized_requests_never_fit() {
+        let policy = Policy::new(&config(Algorithm::Gcra), Duration::ZERO);
+        assert_eq!(policy.retry_after(4, Duration::ZERO), None);
+        let policy = Policy::new(&config(Algorithm::SlidingWindowLog), Duration::ZERO);
+        assert_eq!(policy.retry_after(4, Duration::ZERO), None);
+    }
+
+    #[test]
+    fn state_survives_serialization() {
+        let mut policy = Policy::new(&config(Algorithm::SlidingWindowLog), Duration::ZERO);
+        assert!(policy.try_acquire(2, Duration::from_millis(100)));
+        let json = serde_json::to_value(&policy).unwrap();
+        assert_eq!(json["algorithm"], "sliding_window_log");
+        let mut restored: Policy = serde_json::from_value(json).unwrap();
+        assert_eq!(restored, policy);
+        assert!(restored.try_acquire(1, Duration::from_millis(200)));
+        assert!(!restored.try_acquire(1, Duration::from_millis(300)));
+    }
+}
@@ -0,0 +1,240 @@
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::Deserialize;
+use serde_json::Value;
+use std::str::FromStr;
+
+/// Most decimal places a [`Decimal`] can hold.
+const MAX_PRECISION: u32 = 28;
+
+/// How a converted amount is rounded to the requested number of digits.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub(super) enum Rounding {
+    /// Nearest, ties away from zero.
+    #[default]
+    HalfUp,
+    /// Nearest, ties toward zero.
+    HalfDown,
+    /// Nearest, ties to the even neighbour.
+    HalfEven,
+    /// Away from zero.
+    Up,
+    /// Toward zero.
+    Down,
+    /// Toward positive infinity.
+    Ceiling,
+    /// Toward negative infinity.
+    Floor,
+}
+
+impl Rounding {
+    fn strategy(self) -> RoundingStrategy {
+        match self {
+            Rounding::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            Rounding::HalfDown => RoundingStrategy::MidpointTowardZero,
+            Rounding::HalfEven => RoundingStrategy::MidpointNearestEven,
+            Rounding::Up => RoundingStrategy::AwayFromZero,
+            Rounding::Down => RoundingStrategy::ToZero,
+            Rounding::Ceiling => RoundingStrategy::ToPositiveInfinity,
+            Rounding::Floor => RoundingStrategy::ToNegativeInfinity,
+        }
+    }
+}
+
+/// How many digits of a converted amount are kept.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Digits {
+    #[default]
+    All,
+    DecimalPlaces(u32),
+    Significant(u32),
+}
+
+/// How converted amounts are computed and written out.
+///
+/// By default amounts are multiplied as `f64` and returned as raw JSON
+/// numbers. Asking for a precision rounds that number; `exact` instead does
+/// the arithmetic in decimal and returns strings, so `5 liters` is exactly
+/// `"1.32086"` gallons.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(super) struct NumberFormat {
+    digits: Digits,
+    rounding: Rounding,
+    exact: bool,
+}
+
+impl NumberFormat {
+    pub(super) fn new(
+        precision: Option<u32>,
+        significant_figures: Option<u32>,
+        rounding: Option<Rounding>,
+        exact: bool,
+    ) -> Result<Self, String> {
+        let digits = match (precision, significant_figures) {
+            (Some(_), Some(_)) => {
+                return Err("precision and significant_figures are exclusive".to_string())
+            }
+            (Some(places), None) if places > MAX_PRECISION => {
+                return Err(format!("precision must be at most {}", MAX_PRECISION))
+            }
+            (Some(places), None) => Digits::DecimalPlaces(places),
+            (None, Some(0)) => return Err("significant_figures must be at least 1".to_string()),
+            (None, Some(figures)) if figures > MAX_PRECISION => {
+                return Err(format!(
+                    "significant_figures must be at most {}",
+                    MAX_PRECISION
+                ))
+            }
+            (None, Some(figures)) => Digits::Significant(figures),
+            (None, None) if rounding.is_some() => {
+                return Err("rounding requires precision or significant_figures".to_string())
+            }
+            (None, None) => Digits::All,
+        };
+        Ok(NumberFormat {
+            digits,
+            rounding: rounding.unwrap_or_default(),
+            exact,
+        })
+    }
+
+    /// `amount * factor`, where `factor` is a decimal literal such as
+    /// `"0.264172"`.
+    pub(super) fn convert(&self, amount: f64, factor: &str) -> Result<Value, String> {
+        let out_of_range = || format!("{} is out of range", amount);
+        if self.exact {
+            let factor = Decimal::from_str_exact(factor).expect("conversion factor");
+            let converted = to_decimal(amount)
+                .and_then(|amount| amount.checked_mul(factor))
+                .ok_or_else(out_of_range)?;
+            let rounded = self.round(converted).ok_or_else(out_of_range)?;
+            return Ok(Value::String(rounded.to_string()));
+        }
+
+        let converted = amount * f64::from_str(factor).expect("conversion factor");
+        if self.digits == Digits::All {
+            return Ok(converted.into());
+        }
+        let rounded = to_decimal(converted)
+            .and_then(|converted| self.round(converted))
+            .ok_or_else(out_of_range)?;
+        Ok(f64::try_from(rounded).map_err(|_| out_of_range())?.into())
+    }
+
+    fn round(&self, value: Decimal) -> Option<Decimal> {
+        let strategy = self.rounding.strategy();
+        match self.digits {
+            Digits::All => Some(value.normalize()),
+            Digits::DecimalPlaces(places) => {
+                let mut rounded = value.round_dp_with_strategy(places, strategy);
+                rounded.rescale(places);
+                Some(rounded)
+            }
+            Digits::Significant(figures) => value.round_sf_with_strategy(figures, strategy),
+        }
+    }
+}
+
+/// The decimal a float was written as: `0.1` becomes exactly `0.1`, not the
+/// nearest binary fraction.
+fn to_decimal(value: f64) -> Option<Decimal> {
+    if !value.is_finite() {
+        return None;
+    }
+    Decimal::from_str(&value.to_string())
+        .ok()
+        .or_else(|| Decimal::try_from(value).ok())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn format(
+        precision: Option<u32>,
+        figures: Option<u32>,
+        rounding: Rounding,
+        exact: bool,
+    ) -> NumberFormat {
+        NumberFormat::new(precision, figures, Some(rounding), exact).unwrap()
+    }
+
+    #[test]
+    fn default_output_is_unchanged() {
+        let format = NumberFormat::default();
+        assert_eq!(
+            format.convert(5.0, "0.264172").unwrap(),
+            json!(5.0 * 0.264172)
+        );
+    }
+
+    #[test]
+    fn exact_mode_uses_decimal_arithmetic() {
+        let format = NumberFormat::new(None, None, None, true).unwrap();
+        assert_eq!(format.convert(5.0, "0.264172").unwrap(), json!("1.32086"));
+        assert_eq!(format.convert(0.1, "3.78541").unwrap(), json!("0.378541"));
+    }
+
+    #[test]
+    fn precision_rounds_and_pads() {
+        let exact = format(Some(3), None, Rounding::HalfUp, true);
+        assert_eq!(exact.convert(5.0, "0.264172").unwrap(), json!("1.321"));
+        assert_eq!(exact.convert(2.0, "0.5").unwrap(), json!("1.000"));
+
+        let float = format(Some(2), None, Rounding::HalfUp, false);
+        assert_eq!(float.convert(5.0, "0.264172").unwrap(), json!(1.32));
+    }
+
+    #[test]
+    fn significant_figures() {
+        let exact = format(None, Some(2), Rounding::HalfUp, true);
+        assert_eq!(exact.convert(5.0, "0.264172").unwrap(), json!("1.3"));
+        assert_eq!(exact.convert(1000.0, "3.78541").unwrap(), json!("3800"));
+
+        let float = format(None, Some(4), Rounding::HalfUp, false);
+        assert_eq!(float.convert(0.01, "0.264172").unwrap(), json!(0.002642));
+    }
+
+    #[test]
+    fn rounding_modes() {
+        let cases = [
+            (Rounding::HalfUp, "0.3", "-0.3"),
+            (Rounding::HalfDown, "0.2", "-0.2"),
+            (Rounding::HalfEven, "0.2", "-0.2"),
+            (Rounding::Up, "0.3", "-0.3"),
+            (Rounding::Down, "0.2", "-0.2"),
+            (Rounding::Ceiling, "0.3", "-0.2"),
+            (Rounding::Floor, "0.2", "-0.3"),
+        ];
+        for (rounding, positive, negative) in cases {
+            let format = format(Some(1), None, rounding, true);
+            assert_eq!(
+                format.convert(0.25, "1").unwrap(),
+                json!(positive),
+                "{:?}",
+                rounding
+            );
+            assert_eq!(
+                format.convert(-0.25, "1").unwrap(),
+                json!(negative),
+                "{:?}",
+                rounding
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_formats_are_rejected() {
+        assert!(NumberFormat::new(Some(2), Some(2), None, false).is_err());
+        assert!(NumberFormat::new(None, Some(0), None, false).is_err());
+        assert!(NumberFormat::new(Some(29), None, None, true).is_err());
+        assert!(NumberFormat::new(None, None, Some(Rounding::Up), false).is_err());
+    }
+
+    #[test]
+    fn out_of_range_amounts_are_errors() {
+        let exact = NumberFormat::new(None, None, None, true).unwrap();
+        assert!(exact.convert(1e30, "3.78541").is_err());
+    }
+}
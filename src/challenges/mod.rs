@@ -1,4 +1,5 @@
 use axum::{http::StatusCode, response::IntoResponse, Router};
+use shuttle_runtime::SecretStore;
 use sqlx::PgPool;
 use tower_http::services::ServeDir;
 
@@ -29,12 +30,12 @@ impl<E: Into<anyhow::Error>> From<E> for AppError {
         Self(err.into())
     }
 }
-pub(crate) fn router(pool: PgPool) -> Router {
+pub(crate) fn router(pool: PgPool, secrets: &SecretStore) -> Router {
     Router::new()
         .nest("/", challengeminus1::router())
         .nest("/2", challenge2::router())
-        .nest("/5", challenge5::router())
-        .nest("/9", challenge9::router())
+        .nest("/5", challenge5::router(pool.clone(), secrets))
+        .nest("/9", challenge9::router(pool.clone(), secrets))
         .nest("/12", challenge12::router())
         .nest("/16", challenge16::router())
         .nest("/19", challenge19::router(pool))
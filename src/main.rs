@@ -1,16 +1,20 @@
 use axum::Router;
 
+use shuttle_runtime::SecretStore;
 use sqlx::PgPool;
 
 mod challenges;
 
 #[shuttle_runtime::main]
-async fn main(#[shuttle_shared_db::Postgres] pool: PgPool) -> shuttle_axum::ShuttleAxum {
+async fn main(
+    #[shuttle_shared_db::Postgres] pool: PgPool,
+    #[shuttle_runtime::Secrets] secrets: SecretStore,
+) -> shuttle_axum::ShuttleAxum {
     sqlx::migrate!()
         .run(&pool)
         .await
         .expect("Failed to run migrations");
-    let router = Router::new().nest("/", challenges::router(pool.clone()));
+    let router = Router::new().nest("/", challenges::router(pool.clone(), &secrets));
 
     Ok(router.into())
 }